@@ -0,0 +1,149 @@
+use std::rc::Rc;
+
+use crate::probabilities::combat_stats::{AttackStats, DefenseStats, Rerolls, RollModifier, TargetProfile};
+use crate::probabilities::combat_tree::{CombatConfig, CombatContext, Rule, compute_damages};
+use crate::probabilities::distribution::Distribution;
+
+/// Weapon used by some of the models of an attacking unit, with its own rule sequence.
+///
+/// The models using the weapon, and the extra attacks of their champion, are given by the attack stats.
+#[derive(Clone, Debug)]
+pub struct WeaponProfile {
+    pub attack_stats: AttackStats,
+    /// Shared between the copies made by the builders, rules not being clonable.
+    pub sequence: Rc<Vec<Box<dyn Rule>>>,
+    pub modifier: RollModifier,
+    pub rerolls: Rerolls,
+    /// Flags and attacker keywords checked by conditional rules.
+    pub context: CombatContext,
+}
+
+impl WeaponProfile {
+    pub fn new(attack_stats: AttackStats, sequence: Vec<Box<dyn Rule>>) -> WeaponProfile {
+        WeaponProfile {
+            attack_stats,
+            sequence: Rc::new(sequence),
+            modifier: RollModifier::new_null(),
+            rerolls: Rerolls::none(),
+            context: CombatContext::new(),
+        }
+    }
+
+    pub fn with_modifier(&self, modifier: RollModifier) -> WeaponProfile {
+        let mut new_profile = self.clone();
+        new_profile.modifier = modifier;
+        new_profile
+    }
+
+    pub fn with_rerolls(&self, rerolls: Rerolls) -> WeaponProfile {
+        let mut new_profile = self.clone();
+        new_profile.rerolls = rerolls;
+        new_profile
+    }
+
+    pub fn with_context(&self, context: CombatContext) -> WeaponProfile {
+        let mut new_profile = self.clone();
+        new_profile.context = context;
+        new_profile
+    }
+
+    pub fn config(&self, defense_stats: &DefenseStats) -> CombatConfig {
+        CombatConfig::new_with_modifiers(self.attack_stats.clone(), defense_stats.clone(), self.modifier)
+            .with_rerolls(self.rerolls)
+            .with_context(self.context.clone())
+    }
+
+    pub fn config_against(&self, target: &TargetProfile) -> CombatConfig {
+        self.config(&target.defense_stats).against(target)
+    }
+
+    /// Damage inflicted by all the models using the weapon.
+    pub fn compute_damages(&self, defense_stats: &DefenseStats) -> Distribution<u32> {
+        compute_damages(self.config(defense_stats), &self.sequence)
+    }
+
+    /// Damage inflicted by all the models using the weapon, the keywords and abilities of the target applying.
+    pub fn compute_damages_against(&self, target: &TargetProfile) -> Distribution<u32> {
+        compute_damages(self.config_against(target), &self.sequence)
+    }
+}
+
+/// Unit attacking with several weapon profiles.
+#[derive(Debug, Default)]
+pub struct AttackingUnit {
+    pub profiles: Vec<WeaponProfile>,
+}
+
+impl AttackingUnit {
+    pub fn new(profiles: Vec<WeaponProfile>) -> AttackingUnit {
+        AttackingUnit {profiles}
+    }
+
+    pub fn add_profile(&mut self, profile: WeaponProfile) {
+        self.profiles.push(profile);
+    }
+
+    /// Damage inflicted by each profile, in the order of the profiles.
+    pub fn profile_damages(&self, defense_stats: &DefenseStats) -> Vec<Distribution<u32>> {
+        self.profiles.iter().map(|profile| profile.compute_damages(defense_stats)).collect()
+    }
+
+    /// Damage inflicted by the whole unit, the profiles being rolled independently.
+    pub fn compute_damages(&self, defense_stats: &DefenseStats) -> Distribution<u32> {
+        self.profile_damages(defense_stats)
+            .iter()
+            .fold(Distribution::certain(0), |total, damages| &total + damages)
+    }
+
+    pub fn profile_damages_against(&self, target: &TargetProfile) -> Vec<Distribution<u32>> {
+        self.profiles.iter().map(|profile| profile.compute_damages_against(target)).collect()
+    }
+
+    pub fn compute_damages_against(&self, target: &TargetProfile) -> Distribution<u32> {
+        self.profile_damages_against(target)
+            .iter()
+            .fold(Distribution::certain(0), |total, damages| &total + damages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probabilities::combat_stats::{Characteristic, Reroll};
+    use crate::probabilities::combat_tree::SequenceBuilder;
+
+    fn damages_mean(attack_stats: AttackStats) -> f64 {
+        WeaponProfile::new(attack_stats, SequenceBuilder::new().build())
+            .compute_damages(&DefenseStats::new(4, None))
+            .mean()
+    }
+
+    #[test]
+    fn models_are_counted_once() {
+        let attack_stats = AttackStats::new(Characteristic::Value(2), 3, 4, 0, Characteristic::Value(1));
+        let mean = damages_mean(attack_stats.with_models(5));
+        let expected = damages_mean(attack_stats.with_attacks(Characteristic::Value(10)));
+        assert!((mean - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn champion_attacks_are_counted_once() {
+        let attack_stats = AttackStats::new(Characteristic::Value(0), 3, 4, 0, Characteristic::Value(1));
+        let mean = damages_mean(attack_stats.with_models(5).with_champion_attacks(1));
+        let expected = damages_mean(attack_stats.with_attacks(Characteristic::Value(1)));
+        assert!((mean - expected).abs() < 1e-9);
+        assert!((expected - 2.0 / 3.0 * 0.5 * 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn builders_copy_the_profile() {
+        let attack_stats = AttackStats::new(Characteristic::Value(2), 3, 4, 0, Characteristic::Value(1));
+        let profile = WeaponProfile::new(attack_stats, SequenceBuilder::new().build());
+        let modified = profile.with_modifier(RollModifier::new(1, 0, 0)).with_rerolls(Rerolls::none().with_hit(Reroll::Ones));
+        assert_eq!(profile.modifier.to_hit, 0);
+        assert_eq!(modified.modifier.to_hit, 1);
+        assert!(Rc::ptr_eq(&profile.sequence, &modified.sequence));
+        let defense_stats = DefenseStats::new(4, None);
+        assert!(modified.compute_damages(&defense_stats).mean() > profile.compute_damages(&defense_stats).mean());
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use crate::probabilities::distribution::Distribution;
+use crate::probabilities::duel::{Duel, DuelResult};
+use crate::probabilities::wound_allocation::UnitHealth;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+/// Rounds of a duel fought until one of the units is destroyed, or the round limit is reached.
+#[derive(Debug)]
+pub struct Battle {
+    pub duel: Duel,
+    pub max_rounds: u32,
+}
+
+impl Battle {
+    pub fn new(duel: Duel, max_rounds: u32) -> Battle {
+        Battle {duel, max_rounds}
+    }
+
+    fn is_over(health: &(UnitHealth, UnitHealth)) -> bool {
+        health.0.is_destroyed() || health.1.is_destroyed()
+    }
+
+    /// Exact outcome of the battle, propagating the joint health of both units round by round.
+    ///
+    /// The number of states grows with the product of the wounds of both units, use `simulate`
+    /// for large units.
+    pub fn run(&self) -> BattleResult {
+        let mut cache = HashMap::new();
+        let mut ongoing = Distribution::certain(self.duel.initial_health());
+        let mut ended = Vec::new();
+        let mut rounds = Vec::new();
+
+        for round in 0..self.max_rounds {
+            let (over, fighting): (Vec<_>, Vec<_>) = ongoing.iter().cloned().partition(|(health, _)| Battle::is_over(health));
+            rounds.push((round, over.iter().map(|(_, proba)| proba).sum::<f64>()));
+            ended.extend(over);
+            if fighting.is_empty() {
+                ongoing = Distribution::new(vec![]);
+                break;
+            }
+            ongoing = Distribution::mixture(
+                fighting.into_iter().map(|(health, proba)| (self.duel.cached_round(health, &mut cache), proba)).collect()
+            );
+        }
+        // States left after the last round, whether the battle is over or not
+        rounds.push((self.max_rounds, ongoing.total_probability()));
+        ended.extend(ongoing.values_and_probas());
+        rounds.retain(|(_, proba)| *proba > 0.0);
+
+        self.result(Distribution::new(ended), Distribution::new(rounds))
+    }
+
+    /// Outcome of the battle estimated from simulated battles, reproducible with the same seed.
+    pub fn simulate(&self, samples: u32, seed: u64) -> BattleResult {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let frequency = 1.0 / samples as f64;
+        let mut ended = Vec::new();
+        let mut rounds = Vec::new();
+        for _ in 0..samples {
+            let mut health = self.duel.initial_health();
+            let mut round = 0;
+            while round < self.max_rounds && !Battle::is_over(&health) {
+                health = self.duel.sample_round(health, &mut rng);
+                round += 1;
+            }
+            ended.push((health, frequency));
+            rounds.push((round, frequency));
+        }
+        self.result(Distribution::new(ended), Distribution::new(rounds))
+    }
+
+    fn result(&self, health: Distribution<(UnitHealth, UnitHealth)>, rounds: Distribution<u32>) -> BattleResult {
+        BattleResult {
+            outcome: DuelResult {first: self.duel.first.unit, second: self.duel.second.unit, health},
+            rounds,
+        }
+    }
+}
+
+/// Outcome of a battle.
+#[derive(Clone, Debug)]
+pub struct BattleResult {
+    /// Joint health of both units at the end of the battle.
+    pub outcome: DuelResult,
+    /// Number of rounds fought.
+    pub rounds: Distribution<u32>,
+}
+
+impl BattleResult {
+    fn probability(&self, event: impl Fn(&UnitHealth, &UnitHealth) -> bool) -> f64 {
+        self.outcome.health.iter().filter(|((first, second), _)| event(first, second)).map(|(_, proba)| proba).sum()
+    }
+
+    /// Probability that the first unit destroys the second one and survives.
+    pub fn first_wins_probability(&self) -> f64 {
+        self.probability(|first, second| !first.is_destroyed() && second.is_destroyed())
+    }
+
+    /// Probability that the second unit destroys the first one and survives.
+    pub fn second_wins_probability(&self) -> f64 {
+        self.probability(|first, second| first.is_destroyed() && !second.is_destroyed())
+    }
+
+    /// Probability that both units are still fighting when the round limit is reached.
+    pub fn unresolved_probability(&self) -> f64 {
+        self.probability(|first, second| !first.is_destroyed() && !second.is_destroyed())
+    }
+}
+
+pub fn compute_battle(battle: &Battle) -> BattleResult {
+    battle.run()
+}
+
+pub fn simulate_battle(battle: &Battle, samples: u32, seed: u64) -> BattleResult {
+    battle.simulate(samples, seed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probabilities::combat_stats::{AttackStats, Characteristic, DefenseStats, NO_SAVE, TargetUnit};
+    use crate::probabilities::combat_tree::{CombatConfig, CombatStatus, Rule, SequenceBuilder};
+    use crate::probabilities::duel::Combatant;
+    use crate::probabilities::rules::AttackCharacteristicRule;
+
+    // Every attack wounds, making the battle deterministic with single wound models and 1 damage
+    #[derive(Debug)]
+    struct AutoWoundRule;
+
+    impl Rule for AutoWoundRule {
+        fn apply(&self, status: &CombatStatus, _config: &CombatConfig) -> Distribution<CombatStatus> {
+            Distribution::certain(status.with_wounds(status.attacks))
+        }
+    }
+
+    fn combatant(models: u32, to_hit: u32, sequence: Vec<Box<dyn Rule>>) -> Combatant {
+        let attack_stats = AttackStats::new(Characteristic::Value(1), to_hit, 4, 0, Characteristic::Value(1));
+        Combatant::new(TargetUnit::new(models, 1), attack_stats, DefenseStats::new(NO_SAVE, None), sequence)
+    }
+
+    fn auto_wound(models: u32) -> Combatant {
+        combatant(models, 4, vec![Box::new(AttackCharacteristicRule), Box::new(AutoWoundRule)])
+    }
+
+    #[test]
+    fn deterministic_battles_end_on_a_known_round() {
+        // 5 attacks slay 5 of 8 models and 3 strike back, then 2 slay 2 and 1 strikes back, the last one being slain on round 3
+        let battle = Battle::new(Duel::new(auto_wound(5), auto_wound(8)), 5);
+        let survivors = UnitHealth {models: 1, current_wounds: 1};
+        for result in [battle.run(), battle.simulate(20, 7)] {
+            assert!((result.rounds.probability(&3) - 1.0).abs() < 1e-12);
+            assert!((result.first_wins_probability() - 1.0).abs() < 1e-12);
+            assert!((result.outcome.health.probability(&(survivors, UnitHealth {models: 0, current_wounds: 0})) - 1.0).abs() < 1e-12);
+        }
+
+        let unfinished = Battle::new(Duel::new(auto_wound(5), auto_wound(8)), 2).run();
+        assert!((unfinished.rounds.probability(&2) - 1.0).abs() < 1e-12);
+        assert!((unfinished.unresolved_probability() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn simulations_agree_with_the_exact_outcome() {
+        let duel = || Duel::new(
+            combatant(3, 3, SequenceBuilder::new().build()),
+            combatant(4, 4, SequenceBuilder::new().build()),
+        );
+        let battle = Battle::new(duel(), 4);
+        let exact = battle.run();
+        let samples = 4000;
+        let simulated = battle.simulate(samples, 42);
+
+        // Within 4 standard deviations of the exact probabilities
+        let close = |exact: f64, simulated: f64| (exact - simulated).abs() < 4.0 * (exact * (1.0 - exact) / samples as f64).sqrt();
+        assert!(close(exact.first_wins_probability(), simulated.first_wins_probability()));
+        assert!(close(exact.second_wins_probability(), simulated.second_wins_probability()));
+        assert!(close(exact.unresolved_probability(), simulated.unresolved_probability()));
+        for (round, proba) in exact.rounds.iter() {
+            assert!(close(*proba, simulated.rounds.probability(round)), "round {}", round);
+        }
+
+        let replayed = Battle::new(duel(), 4).simulate(samples, 42);
+        assert_eq!(replayed.rounds.values_and_probas(), simulated.rounds.values_and_probas());
+    }
+}
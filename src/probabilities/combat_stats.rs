@@ -1,29 +1,160 @@
 //use std::collections::HashMap;
+use std::collections::BTreeSet;
+use serde::{Deserialize, Deserializer, Serialize};
 use std::ops::{Add, AddAssign};
-use crate::probabilities::dice::DiceRoll;
+use crate::probabilities::dice::{DiceRoll, DiceRollParseError};
+use crate::probabilities::dice_expression::DiceExpression;
+use crate::probabilities::distribution::Distribution;
 
-#[derive(Clone, Copy, Debug)]
+/// Written as an integer or as a dice expression such as `"D3+1"` in profile files.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "CharacteristicRepr", into = "CharacteristicRepr")]
 pub enum Characteristic {
     Value(u32),
     DiceRoll(DiceRoll),
 }
 
 impl Characteristic {
+    pub fn distribution(&self) -> Distribution<u32> {
+        match self {
+            Characteristic::Value(value) => Distribution::certain(*value),
+            Characteristic::DiceRoll(dice) => dice.distribution(),
+        }
+    }
+
     pub fn values_and_probas(&self) -> Vec<(u32, f64)> {
+        self.distribution().values_and_probas()
+    }
+
+    /// Characteristic increased by a flat bonus.
+    pub fn plus(&self, bonus: u32) -> Characteristic {
         match self {
-            Characteristic::Value(value) => vec![(*value, 1.0)],
-            Characteristic::DiceRoll(dice) => dice.values_and_probas(),
+            Characteristic::Value(value) => Characteristic::Value(value + bonus),
+            Characteristic::DiceRoll(dice) => Characteristic::DiceRoll(match dice {
+                DiceRoll::D6 => DiceRoll::D6Plus(bonus),
+                DiceRoll::D3 => DiceRoll::D3Plus(bonus),
+                DiceRoll::ND6(n) => DiceRoll::ND6Plus(*n, bonus),
+                DiceRoll::ND3(n) => DiceRoll::ND3Plus(*n, bonus),
+                DiceRoll::D6Plus(m) => DiceRoll::D6Plus(m + bonus),
+                DiceRoll::D3Plus(m) => DiceRoll::D3Plus(m + bonus),
+                DiceRoll::ND3Plus(n, m) => DiceRoll::ND3Plus(*n, m + bonus),
+                DiceRoll::ND6Plus(n, m) => DiceRoll::ND6Plus(*n, m + bonus),
+                DiceRoll::Expression(expression) => DiceRoll::Expression(DiceExpression::Sum(
+                    Box::new(expression.clone()),
+                    Box::new(DiceExpression::Constant(bonus as i64))
+                )),
+            }),
         }
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum CharacteristicRepr {
+    Value(u32),
+    DiceRoll(String),
+}
+
+impl TryFrom<CharacteristicRepr> for Characteristic {
+    type Error = DiceRollParseError;
+
+    // Constant expressions such as "3" are read as plain values
+    fn try_from(repr: CharacteristicRepr) -> Result<Characteristic, DiceRollParseError> {
+        match repr {
+            CharacteristicRepr::Value(value) => Ok(Characteristic::Value(value)),
+            CharacteristicRepr::DiceRoll(dice) => match DiceRoll::from_str(dice)? {
+                DiceRoll::Expression(expression) => match expression.constant_value() {
+                    Some(value) if value >= 0 => Ok(Characteristic::Value(value as u32)),
+                    _ => Ok(Characteristic::DiceRoll(DiceRoll::Expression(expression))),
+                },
+                dice => Ok(Characteristic::DiceRoll(dice)),
+            },
+        }
+    }
+}
+
+impl From<Characteristic> for CharacteristicRepr {
+    fn from(characteristic: Characteristic) -> CharacteristicRepr {
+        match characteristic {
+            Characteristic::Value(value) => CharacteristicRepr::Value(value),
+            Characteristic::DiceRoll(dice) => CharacteristicRepr::DiceRoll(dice.to_string()),
+        }
+    }
+}
+
+/// Rolls triggering critical effects, an unmodified 1 is never critical.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CriticalThreshold {
+    pub on: u32,
+    /// Whether the threshold applies to the roll after modifiers.
+    #[serde(default)]
+    pub modified: bool,
+}
+
+impl CriticalThreshold {
+    pub fn new(on: u32) -> CriticalThreshold {
+        CriticalThreshold {on, modified: false}
+    }
+
+    pub fn after_modifiers(on: u32) -> CriticalThreshold {
+        CriticalThreshold {on, modified: true}
+    }
+
+    pub fn unmodified_six() -> CriticalThreshold {
+        CriticalThreshold::new(6)
+    }
+
+    pub fn is_critical(&self, roll: u32, modified_roll: u32) -> bool {
+        roll != 1 && (if self.modified {modified_roll} else {roll}) >= self.on
+    }
+}
+
+/// Roll whose critical successes benefit from a `CriticalBonus`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CriticalTrigger {
+    Hit,
+    Wound,
+}
+
+/// Extra rend and damage applied to the wounds scored with a critical roll.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CriticalBonus {
+    pub trigger: CriticalTrigger,
+    #[serde(default)]
+    pub rend: i32,
+    #[serde(default)]
+    pub damage: u32,
+}
+
+impl CriticalBonus {
+    pub fn new(trigger: CriticalTrigger, rend: i32, damage: u32) -> CriticalBonus {
+        CriticalBonus {trigger, rend, damage}
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AttackStats {
     pub attacks: Characteristic,
     pub to_hit: u32,
     pub to_wound: u32,
-    pub rend: u32,
+    /// Subtracted from save rolls, a negative rend improves them.
+    #[serde(default)]
+    pub rend: i32,
     pub damages: Characteristic,
+    #[serde(default = "CriticalThreshold::unmodified_six")]
+    pub critical_hit: CriticalThreshold,
+    /// Critical wounds are only tracked when a threshold is given, see `critical_wound_threshold`.
+    pub critical_wound: Option<CriticalThreshold>,
+    pub critical_bonus: Option<CriticalBonus>,
+    /// Damage of each mortal wound, the weapon damage if not given.
+    pub mortal_damages: Option<Characteristic>,
+    /// Models attacking with the weapon, each one rolling its own attacks.
+    #[serde(default = "one")]
+    pub models: u32,
+    /// Extra attacks of the unit champion.
+    #[serde(default)]
+    pub champion_attacks: u32,
 }
 
 impl AttackStats {
@@ -31,7 +162,7 @@ impl AttackStats {
         attacks: Characteristic,
         to_hit: u32,
         to_wound: u32,
-        rend: u32,
+        rend: i32,
         damages: Characteristic,
     ) -> AttackStats {
         AttackStats {
@@ -40,88 +171,421 @@ impl AttackStats {
             to_wound,
             rend,
             damages,
+            critical_hit: CriticalThreshold::unmodified_six(),
+            critical_wound: None,
+            critical_bonus: None,
+            mortal_damages: None,
+            models: 1,
+            champion_attacks: 0,
         }
     }
 
     pub fn with_attacks(&self, value: Characteristic) -> AttackStats {
-        AttackStats {
-            attacks: value,
-            to_hit: self.to_hit,
-            to_wound: self.to_wound,
-            rend: self.rend,
-            damages: self.damages
-        }
+        let mut new_stats = self.clone();
+        new_stats.attacks = value;
+        new_stats
     }
 
     pub fn with_damages(&self, value: Characteristic) -> AttackStats {
-        AttackStats {
-            attacks: self.attacks,
-            to_hit: self.to_hit,
-            to_wound: self.to_wound,
-            rend: self.rend,
-            damages: value
-        }        
+        let mut new_stats = self.clone();
+        new_stats.damages = value;
+        new_stats
     }
 
     pub fn with_to_hit(&self, value: u32) -> AttackStats {
-        AttackStats {
-            attacks: self.attacks,
-            to_hit: value,
-            to_wound: self.to_wound,
-            rend: self.rend,
-            damages: self.damages
-        }
+        let mut new_stats = self.clone();
+        new_stats.to_hit = value;
+        new_stats
     }
 
     pub fn with_to_wound(&self, value: u32) -> AttackStats {
-        AttackStats {
-            attacks: self.attacks,
-            to_hit: self.to_hit,
-            to_wound: value,
-            rend: self.rend,
-            damages: self.damages
-        }
+        let mut new_stats = self.clone();
+        new_stats.to_wound = value;
+        new_stats
     }
 
-    pub fn with_rend(&self, value: u32) -> AttackStats {
-        AttackStats {
-            attacks: self.attacks,
-            to_hit: self.to_hit,
-            to_wound: self.to_wound,
-            rend: value,
-            damages: self.damages
+    pub fn with_rend(&self, value: i32) -> AttackStats {
+        let mut new_stats = self.clone();
+        new_stats.rend = value;
+        new_stats
+    }
+
+    pub fn with_critical_hit(&self, value: CriticalThreshold) -> AttackStats {
+        let mut new_stats = self.clone();
+        new_stats.critical_hit = value;
+        new_stats
+    }
+
+    pub fn with_critical_wound(&self, value: Option<CriticalThreshold>) -> AttackStats {
+        let mut new_stats = self.clone();
+        new_stats.critical_wound = value;
+        new_stats
+    }
+
+    pub fn with_critical_bonus(&self, value: Option<CriticalBonus>) -> AttackStats {
+        let mut new_stats = self.clone();
+        new_stats.critical_bonus = value;
+        new_stats
+    }
+
+    pub fn with_mortal_damages(&self, value: Option<Characteristic>) -> AttackStats {
+        let mut new_stats = self.clone();
+        new_stats.mortal_damages = value;
+        new_stats
+    }
+
+    pub fn with_models(&self, value: u32) -> AttackStats {
+        let mut new_stats = self.clone();
+        new_stats.models = value;
+        new_stats
+    }
+
+    pub fn with_champion_attacks(&self, value: u32) -> AttackStats {
+        let mut new_stats = self.clone();
+        new_stats.champion_attacks = value;
+        new_stats
+    }
+
+    /// Attacks of the whole unit, rolled separately for each model.
+    pub fn attacks_distribution(&self) -> Distribution<u32> {
+        self.attacks
+            .distribution()
+            .sum_of(self.models, 0)
+            .map(|attacks| attacks + self.champion_attacks)
+    }
+
+    /// Whether critical successes of the given roll carry the critical bonus.
+    pub fn has_critical_bonus(&self, trigger: CriticalTrigger) -> bool {
+        matches!(self.critical_bonus, Some(bonus) if bonus.trigger == trigger)
+    }
+
+    /// Threshold of the critical wound rolls, an unmodified 6 if a critical bonus is triggered by wound rolls
+    /// without a threshold being given.
+    pub fn critical_wound_threshold(&self) -> Option<CriticalThreshold> {
+        match self.critical_wound {
+            None if self.has_critical_bonus(CriticalTrigger::Wound) => Some(CriticalThreshold::unmodified_six()),
+            threshold => threshold,
         }
     }
 
+    /// Rend applied to critical wounds.
+    pub fn critical_rend(&self) -> i32 {
+        self.rend + self.critical_bonus.map_or(0, |bonus| bonus.rend)
+    }
+
+    /// Damage inflicted by a single critical wound.
+    pub fn critical_damages_distribution(&self) -> Distribution<u32> {
+        let bonus = self.critical_bonus.map_or(0, |bonus| bonus.damage);
+        self.damages.distribution().map(|damage| damage + bonus)
+    }
+
+    /// Damage inflicted by a single mortal wound.
+    pub fn mortal_damages_distribution(&self) -> Distribution<u32> {
+        self.mortal_damages.as_ref().unwrap_or(&self.damages).distribution()
+    }
+
 }
 
 
-#[derive(Clone, Copy, Debug)]
+/// Save characteristic of units without any save.
+pub const NO_SAVE: u32 = 7;
+
+/// Damage negated by ward rolls.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WardScope {
+    #[default]
+    All,
+    MortalWoundsOnly,
+}
+
+/// Damage negation effect, rolled for each damage point or for each wound.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Ward {
+    pub on: u32,
+    #[serde(default)]
+    pub scope: WardScope,
+    /// Whether a successful roll negates all the damage of a wound instead of a single damage point.
+    #[serde(default)]
+    pub per_wound: bool,
+}
+
+impl Ward {
+    pub fn new(on: u32) -> Ward {
+        Ward {on, scope: WardScope::All, per_wound: false}
+    }
+
+    pub fn with_scope(&self, scope: WardScope) -> Ward {
+        Ward {on: self.on, scope, per_wound: self.per_wound}
+    }
+
+    pub fn with_per_wound(&self, per_wound: bool) -> Ward {
+        Ward {on: self.on, scope: self.scope, per_wound}
+    }
+
+    /// Whether the ward applies to the damage of wounds, or of mortal wounds if `mortal` is set.
+    pub fn applies_to(&self, mortal: bool) -> bool {
+        mortal || self.scope == WardScope::All
+    }
+
+    /// Whether an unmodified ward roll succeeds, an unmodified 1 always fails.
+    pub fn is_success(&self, roll: u32) -> bool {
+        roll != 1 && roll >= self.on
+    }
+
+    pub fn success_probability(&self) -> f64 {
+        (1..=6).filter(|roll| self.is_success(*roll)).count() as f64 / 6.0
+    }
+}
+
+/// Reduction of the damage inflicted by each wound, down to a minimum.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DamageReduction {
+    pub amount: u32,
+    pub minimum: u32,
+}
+
+impl DamageReduction {
+    pub fn new(amount: u32, minimum: u32) -> DamageReduction {
+        DamageReduction {amount, minimum}
+    }
+
+    /// Damage below the minimum is left unchanged.
+    pub fn apply(&self, damage: u32) -> u32 {
+        match damage {
+            damage if damage <= self.minimum => damage,
+            damage => damage.saturating_sub(self.amount).max(self.minimum)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DefenseStats {
+    /// Save characteristic, `NO_SAVE` or more if the unit has no save.
+    #[serde(default = "no_save")]
     pub to_save: u32,
-    pub ward: Option<u32>,
+    /// Wards are rolled one after the other, damage has to pass all of them.
+    #[serde(default)]
+    pub wards: Vec<Ward>,
+    /// Applies to the wounds of the attacks, not to mortal wounds.
+    pub damage_reduction: Option<DamageReduction>,
 }
 
 impl DefenseStats {
     pub fn new(to_save: u32, ward: Option<u32>) -> DefenseStats {
-        DefenseStats { to_save: to_save, ward: ward }
+        DefenseStats { to_save, wards: ward.map(Ward::new).into_iter().collect(), damage_reduction: None }
     }
 
     pub fn with_to_save(&self, value: u32) -> DefenseStats {
-        DefenseStats {to_save: value, ward: self.ward}
+        let mut new_stats = self.clone();
+        new_stats.to_save = value;
+        new_stats
     }
 
+    /// Replaces the wards by a single ward against all damage, none if `value` is 0.
     pub fn with_ward(&self, value: u32) -> DefenseStats {
-        if value > 0 {
-            DefenseStats {to_save: self.to_save, ward: Some(value)}
-        }
-        else {
-            DefenseStats {to_save: self.to_save, ward: None}
+        let mut new_stats = self.clone();
+        new_stats.wards = if value > 0 {vec![Ward::new(value)]} else {vec![]};
+        new_stats
+    }
+
+    pub fn with_wards(&self, value: Vec<Ward>) -> DefenseStats {
+        let mut new_stats = self.clone();
+        new_stats.wards = value;
+        new_stats
+    }
+
+    pub fn with_damage_reduction(&self, value: Option<DamageReduction>) -> DefenseStats {
+        let mut new_stats = self.clone();
+        new_stats.damage_reduction = value;
+        new_stats
+    }
+
+    /// Wards applying to the damage of wounds, or of mortal wounds if `mortal` is set.
+    pub fn wards_against(&self, mortal: bool) -> impl Iterator<Item = &Ward> {
+        self.wards.iter().filter(move |ward| ward.applies_to(mortal))
+    }
+
+    /// Damage of a single wound after damage reduction, mortal wounds not being reduced.
+    pub fn reduced_damages(&self, damages: Distribution<u32>, mortal: bool) -> Distribution<u32> {
+        match self.damage_reduction {
+            Some(reduction) if !mortal => damages.map(|damage| reduction.apply(*damage)),
+            _ => damages
         }
     }
+
+    /// Probability that one of the wards negating whole wounds negates a wound.
+    pub fn wound_negation_probability(&self, mortal: bool) -> f64 {
+        1.0 - self.wards_against(mortal)
+            .filter(|ward| ward.per_wound)
+            .map(|ward| 1.0 - ward.success_probability())
+            .product::<f64>()
+    }
+
+    pub fn has_save(&self) -> bool {
+        self.to_save < NO_SAVE
+    }
+
+    /// Whether an unmodified save roll succeeds against the given rend, an unmodified 1 always fails.
+    pub fn is_saved(&self, roll: u32, modifier: &RollModifier, rend: i32) -> bool {
+        self.has_save() && roll != 1 && roll as i32 + modifier.save_modifier(rend) >= self.to_save as i32
+    }
+}
+
+/// Unit receiving the damage, used to allocate damage model by model.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TargetUnit {
+    pub models: u32,
+    pub wounds_per_model: u32,
+    /// Damage already allocated to one of the models.
+    #[serde(default)]
+    pub damage_taken: u32,
+}
+
+impl TargetUnit {
+    pub fn new(models: u32, wounds_per_model: u32) -> TargetUnit {
+        TargetUnit {models, wounds_per_model, damage_taken: 0}
+    }
+
+    pub fn with_damage_taken(&self, value: u32) -> TargetUnit {
+        TargetUnit {models: self.models, wounds_per_model: self.wounds_per_model, damage_taken: value}
+    }
+
+    pub fn total_wounds(&self) -> u32 {
+        self.models * self.wounds_per_model
+    }
+}
+
+// Defaults of the fields omitted in profile files, shared by all the profile structures
+fn one() -> u32 {1}
+
+fn no_save() -> u32 {NO_SAVE}
+
+/// Keywords read from a profile file, stored uppercase.
+pub fn deserialize_keywords<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeSet<String>, D::Error> {
+    let keywords = BTreeSet::<String>::deserialize(deserializer)?;
+    Ok(keywords.into_iter().map(|keyword| keyword.to_uppercase()).collect())
+}
+
+/// Ability of a target unit changing the attacks made against it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DefensiveAbility {
+    /// Added to the hit rolls of the attacks, -1 for a unit harder to hit.
+    HitModifier(i32),
+    WoundModifier(i32),
+    /// Added to the save rolls of the unit, as when it is in cover.
+    SaveModifier(i32),
+    /// Subtracted from a positive rend, which can not become negative.
+    RendReduction(u32),
+}
+
+/// Full profile of a target unit, the keywords being checked by conditional rules.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TargetProfile {
+    pub defense_stats: DefenseStats,
+    pub models: u32,
+    pub wounds_per_model: u32,
+    /// Keywords are stored uppercase, as printed on warscrolls.
+    #[serde(default, deserialize_with = "deserialize_keywords")]
+    pub keywords: BTreeSet<String>,
+    #[serde(default)]
+    pub abilities: Vec<DefensiveAbility>,
 }
 
-#[derive(Clone, Copy, Debug)]
+impl TargetProfile {
+    pub fn new(defense_stats: DefenseStats, models: u32, wounds_per_model: u32) -> TargetProfile {
+        TargetProfile {defense_stats, models, wounds_per_model, keywords: BTreeSet::new(), abilities: Vec::new()}
+    }
+
+    pub fn with_keyword(&self, keyword: &str) -> TargetProfile {
+        let mut new_profile = self.clone();
+        new_profile.keywords.insert(keyword.to_uppercase());
+        new_profile
+    }
+
+    pub fn with_ability(&self, ability: DefensiveAbility) -> TargetProfile {
+        let mut new_profile = self.clone();
+        new_profile.abilities.push(ability);
+        new_profile
+    }
+
+    pub fn has_keyword(&self, keyword: &str) -> bool {
+        self.keywords.contains(&keyword.to_uppercase())
+    }
+
+    pub fn target_unit(&self) -> TargetUnit {
+        TargetUnit::new(self.models, self.wounds_per_model)
+    }
+
+    /// Attack stats and roll modifier of an attack once the abilities of the target are applied.
+    pub fn apply_abilities(&self, attack_stats: &AttackStats, modifier: RollModifier) -> (AttackStats, RollModifier) {
+        self.abilities.iter().fold((attack_stats.clone(), modifier), |(attack_stats, modifier), ability| {
+            match *ability {
+                DefensiveAbility::HitModifier(value) => (attack_stats, modifier + RollModifier::new(value, 0, 0)),
+                DefensiveAbility::WoundModifier(value) => (attack_stats, modifier + RollModifier::new(0, value, 0)),
+                DefensiveAbility::SaveModifier(value) => (attack_stats, modifier + RollModifier::new(0, 0, value)),
+                DefensiveAbility::RendReduction(value) => {
+                    let rend = if attack_stats.rend > 0 {(attack_stats.rend - value as i32).max(0)} else {attack_stats.rend};
+                    (attack_stats.with_rend(rend), modifier)
+                }
+            }
+        })
+    }
+}
+
+/// Which dice of a roll may be rerolled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reroll {
+    #[default]
+    None,
+    /// Reroll unmodified rolls of 1.
+    Ones,
+    /// Reroll every failed roll.
+    Failures,
+    /// Reroll a single failed die of the roll.
+    #[serde(rename = "single")]
+    SingleDie,
+    /// Reroll every roll that is not a critical.
+    NonCritical,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rerolls {
+    pub hit: Reroll,
+    pub wound: Reroll,
+    pub save: Reroll,
+}
+
+impl Rerolls {
+    pub fn new(hit: Reroll, wound: Reroll, save: Reroll) -> Rerolls {
+        Rerolls {hit, wound, save}
+    }
+
+    pub fn none() -> Rerolls {
+        Rerolls {hit: Reroll::None, wound: Reroll::None, save: Reroll::None}
+    }
+
+    pub fn with_hit(&self, value: Reroll) -> Rerolls {
+        Rerolls {hit: value, wound: self.wound, save: self.save}
+    }
+
+    pub fn with_wound(&self, value: Reroll) -> Rerolls {
+        Rerolls {hit: self.hit, wound: value, save: self.save}
+    }
+
+    pub fn with_save(&self, value: Reroll) -> Rerolls {
+        Rerolls {hit: self.hit, wound: self.wound, save: value}
+    }
+}
+
+// Save rolls can not be improved by more than 1
+const SAVE_MODIFIER_CAP: i32 = 1;
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RollModifier {
     pub to_hit: i32,
     pub to_wound: i32,
@@ -158,8 +622,10 @@ impl RollModifier {
         RollModifier::apply_modifier(value, self.to_wound, -1, 1)
     }
 
-    pub fn apply_to_save_modifier(&self, value: u32) -> u32 {
-        RollModifier::apply_modifier(value, self.to_wound, i32::MIN, 1)
+    /// Total modifier of a save roll, rend counting as a negative modifier.
+    /// Positive modifiers are capped but rend is not, a negative rend improving the roll beyond the cap.
+    pub fn save_modifier(&self, rend: i32) -> i32 {
+        self.to_save.min(SAVE_MODIFIER_CAP) - rend
     }
 }
 
@@ -181,3 +647,47 @@ impl AddAssign for RollModifier {
         self.to_save += other.to_save
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abilities_change_the_attacks_against_the_target() {
+        let target = TargetProfile::new(DefenseStats::new(4, None), 10, 1)
+            .with_ability(DefensiveAbility::HitModifier(-1))
+            .with_ability(DefensiveAbility::RendReduction(1));
+        let attack_stats = |rend: i32| AttackStats::new(Characteristic::Value(4), 3, 4, rend, Characteristic::Value(1));
+        for (rend, expected_rend) in [(2, 1), (1, 0), (0, 0), (-1, -1)] {
+            let (stats, modifier) = target.apply_abilities(&attack_stats(rend), RollModifier::new(0, 1, 0));
+            assert_eq!(stats.rend, expected_rend);
+            assert_eq!((modifier.to_hit, modifier.to_wound, modifier.to_save), (-1, 1, 0));
+        }
+    }
+
+    #[test]
+    fn save_modifiers_are_capped_but_rend_is_not() {
+        let modifier = |to_save: i32| RollModifier::new(0, 0, to_save);
+        for (to_save, rend, expected) in [(0, 0, 0), (0, 2, -2), (0, -1, 1), (1, 0, 1), (2, 0, 1), (3, 1, 0), (2, -1, 2), (-2, 1, -3)] {
+            assert_eq!(modifier(to_save).save_modifier(rend), expected, "to_save {} rend {}", to_save, rend);
+        }
+    }
+
+    #[test]
+    fn saves_follow_the_modifiers() {
+        let saved_rolls = |defense: &DefenseStats, to_save: i32, rend: i32| {
+            (1..=6).filter(|roll| defense.is_saved(*roll, &RollModifier::new(0, 0, to_save), rend)).collect::<Vec<u32>>()
+        };
+        let defense = DefenseStats::new(4, None);
+        assert_eq!(saved_rolls(&defense, 0, 0), vec![4, 5, 6]);
+        assert_eq!(saved_rolls(&defense, 0, 2), vec![6]);
+        assert_eq!(saved_rolls(&defense, 3, 0), vec![3, 4, 5, 6]);
+        assert_eq!(saved_rolls(&defense, 0, -3), vec![2, 3, 4, 5, 6]);
+
+        // A 7+ save is no save, whatever the modifiers
+        let seven_plus = DefenseStats::new(7, None);
+        assert!(!seven_plus.has_save());
+        assert!(saved_rolls(&seven_plus, 1, -1).is_empty());
+        assert!(saved_rolls(&DefenseStats::new(NO_SAVE, None), 1, -3).is_empty());
+    }
+}
@@ -1,15 +1,30 @@
-use crate::probabilities::combat_stats::{AttackStats,DefenseStats, RollModifier};
-use std::collections::HashMap;
+use crate::probabilities::combat_stats::{AttackStats,DefenseStats, Rerolls, RollModifier, TargetProfile, deserialize_keywords};
+use crate::probabilities::distribution::Distribution;
+use crate::probabilities::errors::StatsError;
+use crate::probabilities::rules::StandardRule;
+use crate::probabilities::statistics::DistributionSummary;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
 use std::fmt;
 
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct CombatStatus {
     pub attacks: u32,
     pub hits: u32,
+    /// Hits scored with a critical hit roll.
+    pub critical_hits: u32,
     pub wounds: u32,
+    /// Wounds scored with a critical roll, suffering the critical bonus if any.
+    pub critical_wounds: u32,
+    /// Mortal wounds skip save rolls and inflict their own damage.
     pub mortal_wounds: u32,
     pub damages: u32,
+    /// Damage inflicted by mortal wounds, kept apart from `damages` for ward rolls.
+    pub mortal_damages: u32,
+    /// Damage of the wounds negated by per-wound wards, only discarded once wards are rolled.
+    pub warded_damages: u32,
 }
 
 impl CombatStatus {
@@ -17,71 +32,151 @@ impl CombatStatus {
         CombatStatus {
             attacks: 0,
             hits: 0,
+            critical_hits: 0,
             wounds: 0,
+            critical_wounds: 0,
             mortal_wounds: 0,
             damages: 0,
+            mortal_damages: 0,
+            warded_damages: 0,
         }
     }
 
     pub fn new_with_values(attacks: u32, hits: u32, wounds: u32, mortal_wounds: u32, damages: u32) -> CombatStatus {
-        CombatStatus{attacks, hits, wounds, mortal_wounds, damages}
+        CombatStatus{attacks, hits, critical_hits: 0, wounds, critical_wounds: 0, mortal_wounds, damages, mortal_damages: 0, warded_damages: 0}
     }
 
     pub fn with_attacks(&self, attacks: u32) -> CombatStatus {
-        let mut new_status = self.clone();
+        let mut new_status = *self;
         new_status.attacks = attacks;
         new_status
     }
 
     pub fn with_hits(&self, hits: u32) -> CombatStatus {
-        let mut new_status = self.clone();
+        let mut new_status = *self;
         new_status.hits = hits;
         new_status
     }
 
+    pub fn with_critical_hits(&self, critical_hits: u32) -> CombatStatus {
+        let mut new_status = *self;
+        new_status.critical_hits = critical_hits;
+        new_status
+    }
+
     pub fn with_wounds(&self, wounds: u32) -> CombatStatus {
-        let mut new_status = self.clone();
+        let mut new_status = *self;
         new_status.wounds = wounds;
         new_status
     }
 
+    pub fn with_critical_wounds(&self, critical_wounds: u32) -> CombatStatus {
+        let mut new_status = *self;
+        new_status.critical_wounds = critical_wounds;
+        new_status
+    }
+
     pub fn with_mortal_wounds(&self, mortal_wounds: u32) -> CombatStatus {
-        let mut new_status = self.clone();
+        let mut new_status = *self;
         new_status.mortal_wounds = mortal_wounds;
         new_status
     }
 
     pub fn with_damages(&self, damages: u32) -> CombatStatus {
-        let mut new_status = self.clone();
+        let mut new_status = *self;
         new_status.damages = damages;
         new_status
     }
 
-/*     pub fn with_attribute(&self, attribute: CombatStatusAttribute, value: u32) -> CombatStatus {
-        match attribute {
-            CombatStatusAttribute::Attacks => self.with_attacks(value),
-            CombatStatusAttribute::Hits => self.with_hits(value),
-            CombatStatusAttribute::Wounds => self.with_wounds(value),
-            CombatStatusAttribute::MortalWounds => self.with_mortal_wounds(value),
-            CombatStatusAttribute::Damages => self.with_damages(value)
-        }
-    } */
+    pub fn with_mortal_damages(&self, mortal_damages: u32) -> CombatStatus {
+        let mut new_status = *self;
+        new_status.mortal_damages = mortal_damages;
+        new_status
+    }
+
+    pub fn with_warded_damages(&self, warded_damages: u32) -> CombatStatus {
+        let mut new_status = *self;
+        new_status.warded_damages = warded_damages;
+        new_status
+    }
+
+    pub fn total_damages(&self) -> u32 {
+        self.damages + self.mortal_damages + self.warded_damages
+    }
 }
 
-#[derive(Clone, Copy, Debug)]
-pub enum CombatStatusAttribute {
-    Attacks,
-    Hits,
-    Wounds,
-    MortalWounds,
-    Damages
+/// Game state conditional rules are checked against.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CombatContext {
+    /// Named situations, such as "charged", set for the attack.
+    pub flags: BTreeSet<String>,
+    /// Keywords are stored uppercase, as printed on warscrolls.
+    #[serde(deserialize_with = "deserialize_keywords")]
+    pub attacker_keywords: BTreeSet<String>,
+    #[serde(deserialize_with = "deserialize_keywords")]
+    pub target_keywords: BTreeSet<String>,
+    /// Models in the target unit, if known.
+    pub target_models: Option<u32>,
 }
 
-#[derive(Clone, Copy, Debug)]
+impl CombatContext {
+    pub fn new() -> CombatContext {
+        CombatContext::default()
+    }
+
+    pub fn with_flag(&self, flag: &str) -> CombatContext {
+        let mut new_context = self.clone();
+        new_context.flags.insert(flag.to_string());
+        new_context
+    }
+
+    pub fn with_attacker_keyword(&self, keyword: &str) -> CombatContext {
+        let mut new_context = self.clone();
+        new_context.attacker_keywords.insert(keyword.to_uppercase());
+        new_context
+    }
+
+    pub fn with_target_keyword(&self, keyword: &str) -> CombatContext {
+        let mut new_context = self.clone();
+        new_context.target_keywords.insert(keyword.to_uppercase());
+        new_context
+    }
+
+    pub fn with_target_models(&self, models: u32) -> CombatContext {
+        let mut new_context = self.clone();
+        new_context.target_models = Some(models);
+        new_context
+    }
+
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.contains(flag)
+    }
+
+    pub fn attacker_has_keyword(&self, keyword: &str) -> bool {
+        self.attacker_keywords.contains(&keyword.to_uppercase())
+    }
+
+    pub fn target_has_keyword(&self, keyword: &str) -> bool {
+        self.target_keywords.contains(&keyword.to_uppercase())
+    }
+
+    /// Context with the keywords and model count of the target replaced by those of `target`.
+    pub fn with_target(&self, target: &TargetProfile) -> CombatContext {
+        let mut new_context = self.clone();
+        new_context.target_keywords = target.keywords.clone();
+        new_context.target_models = Some(target.models);
+        new_context
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct CombatConfig {
     pub attack_stats: AttackStats,
     pub defense_stats: DefenseStats,
-    pub modifier: RollModifier
+    pub modifier: RollModifier,
+    pub rerolls: Rerolls,
+    pub context: CombatContext,
 }
 
 impl CombatConfig {
@@ -92,7 +187,9 @@ impl CombatConfig {
         CombatConfig {
             attack_stats: attack_stats,
             defense_stats: defense_stats,
-            modifier: RollModifier::new_null()
+            modifier: RollModifier::new_null(),
+            rerolls: Rerolls::none(),
+            context: CombatContext::new(),
         }
     }
 
@@ -101,117 +198,317 @@ impl CombatConfig {
         defense_stats: DefenseStats,
         modifier: RollModifier
     ) -> CombatConfig {
-        CombatConfig {attack_stats, defense_stats, modifier}
+        CombatConfig {attack_stats, defense_stats, modifier, rerolls: Rerolls::none(), context: CombatContext::new()}
     }
-}
 
-#[derive(Clone, Debug)]
-pub struct CombatNode {
-    pub status: CombatStatus,
-    pub config: CombatConfig,
-    pub probability: f64,
-    pub children: Vec<CombatNode>,
-}
+    pub fn with_rerolls(&self, rerolls: Rerolls) -> CombatConfig {
+        let mut new_config = self.clone();
+        new_config.rerolls = rerolls;
+        new_config
+    }
 
-impl CombatNode {
-    pub fn new(status: CombatStatus, config: CombatConfig, probability: f64) -> CombatNode {
-        CombatNode {
-            status,
-            config,
-            probability,
-            children: Vec::new(),
+    pub fn with_context(&self, context: CombatContext) -> CombatConfig {
+        let mut new_config = self.clone();
+        new_config.context = context;
+        new_config
+    }
+
+    /// Configuration of the same attack made against `target`, whose abilities apply.
+    pub fn against(&self, target: &TargetProfile) -> CombatConfig {
+        let (attack_stats, modifier) = target.apply_abilities(&self.attack_stats, self.modifier);
+        CombatConfig {
+            attack_stats,
+            defense_stats: target.defense_stats.clone(),
+            modifier,
+            rerolls: self.rerolls,
+            context: self.context.with_target(target),
         }
     }
+}
+
+/// Game rule, seen as a random transition between combat states.
+///
+/// The same transition drives both the exact `CombatTree` and the Monte Carlo simulations.
+pub trait Rule: fmt::Debug {
+    /// Distribution of the states reached from `status`, which is kept as is if the rule does not apply.
+    fn apply(&self, status: &CombatStatus, config: &CombatConfig) -> Distribution<CombatStatus>;
+
+    /// Step of the attack sequence performed by the rule, custom rules having none.
+    fn stage(&self) -> Option<RuleStage> {None}
+
+    /// Identifier of the rules provided by the crate, custom rules having none.
+    fn standard_rule(&self) -> Option<StandardRule> {None}
 
-    pub fn add_child(&mut self, node: CombatNode) {
-        self.children.push(node);
+    /// Draws a single outcome of the rule, by default following the distribution given by `apply`.
+    fn sample(&self, status: &CombatStatus, config: &CombatConfig, rng: &mut dyn RngCore) -> CombatStatus {
+        self.apply(status, config).sample(rng).unwrap_or(*status)
     }
+}
 
-    pub fn leaves(&self) -> Vec<&CombatNode> {
-        if self.children.is_empty() {
-            return vec![self];
-        }
 
-        let mut leaves = Vec::new();
-        for child in &self.children {
-            leaves.extend(child.leaves());
+/// Steps of the attack sequence, in the order they are resolved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RuleStage {
+    Attacks,
+    Hit,
+    Wound,
+    Save,
+    Damages,
+    Ward
+}
+
+impl RuleStage {
+    fn previous(&self) -> Option<RuleStage> {
+        match self {
+            RuleStage::Attacks => None,
+            RuleStage::Hit => Some(RuleStage::Attacks),
+            RuleStage::Wound => Some(RuleStage::Hit),
+            RuleStage::Save => Some(RuleStage::Wound),
+            RuleStage::Damages => Some(RuleStage::Save),
+            RuleStage::Ward => Some(RuleStage::Damages),
         }
-        leaves
     }
+}
 
-    pub fn leaves_mut(&mut self) -> Vec<&mut CombatNode> {
-        if self.children.is_empty() {
-            return vec![self];
+/// Checks that the steps of a sequence are in order, each step following the previous one.
+///
+/// Custom rules may be placed anywhere, and may stand for the steps missing before the next rule.
+pub fn validate_sequence(sequence: &[Box<dyn Rule>]) -> Result<(), StatsError> {
+    let mut previous: Option<(&Box<dyn Rule>, RuleStage)> = None;
+    let mut custom_rule = false;
+    for rule in sequence {
+        let Some(stage) = rule.stage() else {
+            custom_rule = true;
+            continue;
+        };
+        match previous {
+            Some((previous_rule, previous_stage)) if previous_stage == stage => {
+                return Err(StatsError::InvalidSequence(format!("{:?} repeats the {:?} step of {:?}", rule, stage, previous_rule)));
+            }
+            Some((previous_rule, previous_stage)) if previous_stage > stage => {
+                return Err(StatsError::InvalidSequence(format!("{:?} must come before {:?}", rule, previous_rule)));
+            }
+            _ if !custom_rule && previous.map(|(_, stage)| stage) != stage.previous() => {
+                return Err(StatsError::InvalidSequence(format!(
+                    "{:?} misses the {:?} step before it", rule, stage.previous().unwrap_or(stage)
+                )));
+            }
+            _ => {}
         }
+        previous = Some((rule, stage));
+        custom_rule = false;
+    }
+    Ok(())
+}
 
-        let mut leaves = Vec::new();
-        for child in self.children.iter_mut() {
-            leaves.extend(child.leaves_mut());
-        }
-        leaves
+/// Rule resolving the hit rolls, depending on the effect of critical hits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CriticalHitEffect {
+    None,
+    MortalWound,
+    AutoWound,
+    DoubleHit
+}
+
+/// Builds the standard attack sequence.
+///
+/// Rerolls and modifiers are not steps of the sequence, they are given by the `CombatConfig`.
+#[derive(Clone, Copy, Debug)]
+pub struct SequenceBuilder {
+    pub critical_hit: CriticalHitEffect,
+    /// Whether damage is rolled, `compute_kills` rolling it anyway.
+    pub damages: bool,
+    /// Whether wards are rolled against each damage point, only when damage is rolled.
+    pub ward: bool,
+}
+
+impl SequenceBuilder {
+    pub fn new() -> SequenceBuilder {
+        SequenceBuilder {critical_hit: CriticalHitEffect::None, damages: true, ward: true}
     }
 
-    pub fn apply_rule(&mut self, rule: &dyn Rule) {
-        let children = rule.apply(self);
-        for child in children {
-            self.add_child(child)
+    pub fn with_critical_hit(&self, critical_hit: CriticalHitEffect) -> SequenceBuilder {
+        let mut new_builder = *self;
+        new_builder.critical_hit = critical_hit;
+        new_builder
+    }
+
+    pub fn with_damages(&self, damages: bool) -> SequenceBuilder {
+        let mut new_builder = *self;
+        new_builder.damages = damages;
+        new_builder
+    }
+
+    pub fn with_ward(&self, ward: bool) -> SequenceBuilder {
+        let mut new_builder = *self;
+        new_builder.ward = ward;
+        new_builder
+    }
+
+    pub fn build(&self) -> Vec<Box<dyn Rule>> {
+        self.standard_rules().iter().map(StandardRule::rule).collect()
+    }
+
+    /// Validated counterpart of `build` for sequences made of other rules, which are checked by `validate_sequence`.
+    pub fn custom(sequence: Vec<Box<dyn Rule>>) -> Result<Vec<Box<dyn Rule>>, StatsError> {
+        validate_sequence(&sequence)?;
+        Ok(sequence)
+    }
+
+    /// Rules of the sequence, as built by `build`.
+    pub fn standard_rules(&self) -> Vec<StandardRule> {
+        let mut sequence = vec![StandardRule::AttackCharacteristic];
+        sequence.push(match self.critical_hit {
+            CriticalHitEffect::None => StandardRule::Hit,
+            CriticalHitEffect::MortalWound => StandardRule::CritMortalWound,
+            CriticalHitEffect::AutoWound => StandardRule::CritAutoWound,
+            CriticalHitEffect::DoubleHit => StandardRule::CritDoubleHit,
+        });
+        sequence.push(StandardRule::Wound);
+        sequence.push(StandardRule::Save);
+        if self.damages {
+            sequence.push(StandardRule::Damages);
+            if self.ward {
+                sequence.push(StandardRule::Ward);
+            }
         }
+        sequence
     }
 }
 
-pub trait Rule: fmt::Debug {
-    fn apply(&self, node: &CombatNode) -> Vec<CombatNode>;
+impl Default for SequenceBuilder {
+    fn default() -> SequenceBuilder {
+        SequenceBuilder::new()
+    }
 }
 
-
+/// Distribution of the combat states reached through a sequence of rules.
+///
+/// Identical states are merged after each rule, so the number of states stays
+/// polynomial in the number of attacks. All states share the same configuration.
 pub struct CombatTree {
-    root: CombatNode
+    config: CombatConfig,
+    states: Distribution<CombatStatus>
 }
 
 impl CombatTree {
     pub fn new(config: CombatConfig) -> CombatTree {
         CombatTree {
-            root: CombatNode::new(
-                CombatStatus::new(),
-                config,
-                1.0
-            )
+            config,
+            states: Distribution::certain(CombatStatus::new())
         }
     }
 
     pub fn build(&mut self, sequence: &Vec<Box<dyn Rule>>) -> () {
         for rule in sequence {
-            let leaves = self.root.leaves_mut();
-            for leaf in leaves {
-                //println!("{:?}: {:?}", rule, leaf.status);
-                leaf.apply_rule(rule.as_ref());
-            }
+            self.apply_rule(rule.as_ref());
         }
     }
 
-    pub fn retrieve_damages_probas(&self) -> Vec<(u32, f64)> {
-        let damages_probas: Vec<(u32, f64)> = self.root.leaves().iter().map(
-            |node| (node.status.damages, node.probability)
-        ).collect();
+    /// Applies a rule to every state.
+    pub fn apply_rule(&mut self, rule: &dyn Rule) {
+        self.states = self.states.and_then(|status| rule.apply(status, &self.config));
+    }
 
+    pub fn states(&self) -> &Distribution<CombatStatus> {
+        &self.states
+    }
 
-        let mut damages_proba_grouped = HashMap::new();
-        for (value, proba) in damages_probas {
-            let entry = damages_proba_grouped.entry(value).or_insert(0.0);
-            *entry += proba;
-        }
+    pub fn retrieve_damages_distribution(&self) -> Distribution<u32> {
+        self.states.map(|status| status.total_damages())
+    }
 
-        let mut damages_probas_vec: Vec<(u32, f64)> = damages_proba_grouped
-            .iter().map(|(value, proba)| (*value, *proba))
-            .collect();
-        damages_probas_vec.sort_by(|a, b| a.0.cmp(&b.0));
-        damages_probas_vec
+    /// Distribution of the unsaved wounds, including mortal wounds.
+    pub fn retrieve_wounds_distribution(&self) -> Distribution<u32> {
+        self.states.map(|status| status.wounds + status.critical_wounds + status.mortal_wounds)
+    }
+
+    /// Joint distribution of the unsaved wounds, critical wounds and mortal wounds.
+    pub fn retrieve_wound_streams_distribution(&self) -> Distribution<Vec<u32>> {
+        self.states.map(|status| vec![status.wounds, status.critical_wounds, status.mortal_wounds])
     }
-}
 
+    pub fn retrieve_damages_probas(&self) -> Vec<(u32, f64)> {
+        self.retrieve_damages_distribution().values_and_probas()
+    }
+}
 
-pub fn compute_damages(config: CombatConfig, sequence: &Vec<Box<dyn Rule>>) -> Vec<(u32, f64)> {
+/// Distribution of the damage inflicted by the sequence.
+///
+/// The order of the steps is not checked here: sequences not built by `SequenceBuilder::build` should go
+/// through `SequenceBuilder::custom`, as the Python bindings do.
+pub fn compute_damages(config: CombatConfig, sequence: &Vec<Box<dyn Rule>>) -> Distribution<u32> {
     let mut tree = CombatTree::new(config);
     tree.build(sequence);
-    tree.retrieve_damages_probas()
+    tree.retrieve_damages_distribution()
+}
+
+pub fn compute_damages_summary(config: CombatConfig, sequence: &Vec<Box<dyn Rule>>) -> DistributionSummary {
+    compute_damages(config, sequence).summary()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probabilities::combat_stats::{AttackStats, Characteristic, DefenseStats};
+    use crate::probabilities::dice::DiceRoll;
+    use crate::probabilities::rules::{AttackCharacteristicRule, CritMortalWoundRule, DamagesRule, HitRule, SaveRule, WoundRule};
+
+    // Rule standing for steps the crate does not provide
+    #[derive(Debug)]
+    struct CustomRule;
+
+    impl Rule for CustomRule {
+        fn apply(&self, status: &CombatStatus, _config: &CombatConfig) -> Distribution<CombatStatus> {
+            Distribution::certain(*status)
+        }
+    }
+
+    #[test]
+    fn identical_states_are_merged() {
+        // 40 attacks with D3 damage, succeeding on 4+ at every step
+        let damages = Characteristic::DiceRoll(DiceRoll::from_str("D3".to_string()).unwrap());
+        let attack_stats = AttackStats::new(Characteristic::Value(40), 4, 4, 0, damages);
+        let mut tree = CombatTree::new(CombatConfig::new(attack_stats, DefenseStats::new(4, None)));
+        for rule in SequenceBuilder::new().build() {
+            tree.apply_rule(rule.as_ref());
+            // At most one state per number of hits and critical hits, wounds and critical wounds or damage
+            assert!(tree.states().len() <= 41 * 41, "{} states after {:?}", tree.states().len(), rule);
+        }
+        let damages = tree.retrieve_damages_distribution();
+        assert!(damages.len() <= 121);
+        assert!((damages.total_probability() - 1.0).abs() < 1e-9);
+        assert!((damages.mean() - 40.0 / 8.0 * 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn standard_sequences_are_valid() {
+        let effects = [CriticalHitEffect::None, CriticalHitEffect::MortalWound, CriticalHitEffect::AutoWound, CriticalHitEffect::DoubleHit];
+        for effect in effects {
+            for (damages, ward) in [(true, true), (true, false), (false, true)] {
+                let sequence = SequenceBuilder::new().with_critical_hit(effect).with_damages(damages).with_ward(ward).build();
+                assert!(validate_sequence(&sequence).is_ok(), "{:?}", sequence);
+            }
+        }
+    }
+
+    #[test]
+    fn sequences_out_of_order_are_rejected() {
+        let custom_steps: Vec<Box<dyn Rule>> = vec![
+            Box::new(CustomRule), Box::new(HitRule), Box::new(WoundRule), Box::new(CustomRule), Box::new(DamagesRule)
+        ];
+        assert!(validate_sequence(&custom_steps).is_ok());
+        assert_eq!(SequenceBuilder::custom(custom_steps).map(|sequence| sequence.len()).ok(), Some(5));
+
+        let invalid_sequences: Vec<Vec<Box<dyn Rule>>> = vec![
+            vec![Box::new(AttackCharacteristicRule), Box::new(HitRule), Box::new(SaveRule), Box::new(WoundRule)],
+            vec![Box::new(AttackCharacteristicRule), Box::new(HitRule), Box::new(SaveRule)],
+            vec![Box::new(AttackCharacteristicRule), Box::new(HitRule), Box::new(CritMortalWoundRule)],
+            vec![Box::new(HitRule), Box::new(WoundRule)],
+        ];
+        for sequence in invalid_sequences {
+            assert!(matches!(validate_sequence(&sequence), Err(StatsError::InvalidSequence(_))), "{:?}", sequence);
+            assert!(matches!(SequenceBuilder::custom(sequence), Err(StatsError::InvalidSequence(_))));
+        }
+    }
 }
@@ -0,0 +1,223 @@
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::probabilities::combat_stats::Reroll;
+use crate::probabilities::combat_tree::{CombatConfig, CombatContext, CombatStatus, Rule, RuleStage};
+use crate::probabilities::distribution::Distribution;
+use crate::probabilities::rules::SaveRule;
+
+/// Condition on the game state of an attack.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    Flag(String),
+    AttackerKeyword(String),
+    TargetKeyword(String),
+    TargetModelsAtLeast(u32),
+    Not(Box<Condition>),
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+}
+
+impl Condition {
+    /// An unknown target size never satisfies a model count condition.
+    pub fn holds(&self, context: &CombatContext) -> bool {
+        match self {
+            Condition::Flag(flag) => context.has_flag(flag),
+            Condition::AttackerKeyword(keyword) => context.attacker_has_keyword(keyword),
+            Condition::TargetKeyword(keyword) => context.target_has_keyword(keyword),
+            Condition::TargetModelsAtLeast(models) => context.target_models.is_some_and(|target_models| target_models >= *models),
+            Condition::Not(condition) => !condition.holds(context),
+            Condition::All(conditions) => conditions.iter().all(|condition| condition.holds(context)),
+            Condition::Any(conditions) => conditions.iter().any(|condition| condition.holds(context)),
+        }
+    }
+}
+
+/// Change made to the combat configuration when a condition holds.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigChange {
+    HitModifier(i32),
+    WoundModifier(i32),
+    SaveModifier(i32),
+    Rend(i32),
+    ExtraDamage(u32),
+    ExtraAttacks(u32),
+    HitReroll(Reroll),
+    WoundReroll(Reroll),
+    SaveReroll(Reroll),
+}
+
+impl ConfigChange {
+    pub fn apply(&self, config: &CombatConfig) -> CombatConfig {
+        let mut new_config = config.clone();
+        match *self {
+            ConfigChange::HitModifier(modifier) => new_config.modifier.to_hit += modifier,
+            ConfigChange::WoundModifier(modifier) => new_config.modifier.to_wound += modifier,
+            ConfigChange::SaveModifier(modifier) => new_config.modifier.to_save += modifier,
+            ConfigChange::Rend(rend) => new_config.attack_stats.rend += rend,
+            ConfigChange::ExtraDamage(damage) => {
+                let damages = config.attack_stats.damages.plus(damage);
+                new_config.attack_stats = config.attack_stats.with_damages(damages);
+            },
+            ConfigChange::ExtraAttacks(attacks) => {
+                let attacks = config.attack_stats.attacks.plus(attacks);
+                new_config.attack_stats = config.attack_stats.with_attacks(attacks);
+            },
+            ConfigChange::HitReroll(reroll) => new_config.rerolls = config.rerolls.with_hit(reroll),
+            ConfigChange::WoundReroll(reroll) => new_config.rerolls = config.rerolls.with_wound(reroll),
+            ConfigChange::SaveReroll(reroll) => new_config.rerolls = config.rerolls.with_save(reroll),
+        }
+        new_config
+    }
+}
+
+/// How a conditional rule depends on its condition.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConditionalMode {
+    /// The rule is only applied when the condition holds, and skipped otherwise.
+    Apply,
+    /// The rule is always applied, with the changes when the condition holds.
+    Modify(Vec<ConfigChange>),
+}
+
+/// Rule only applied, or only modified, when a condition holds for the context of the attack.
+///
+/// Configuration changes only affect the wrapped step: "+1 to hit" has to wrap the hit rule,
+/// "+1 damage" the damages rule, which `compute_kills` also applies wound by wound.
+#[derive(Debug)]
+pub struct ConditionalRule {
+    pub condition: Condition,
+    pub rule: Box<dyn Rule>,
+    pub mode: ConditionalMode,
+}
+
+impl ConditionalRule {
+    pub fn new(condition: Condition, rule: Box<dyn Rule>, mode: ConditionalMode) -> ConditionalRule {
+        ConditionalRule {condition, rule, mode}
+    }
+
+    pub fn enabled_if(condition: Condition, rule: Box<dyn Rule>) -> ConditionalRule {
+        ConditionalRule::new(condition, rule, ConditionalMode::Apply)
+    }
+
+    pub fn modified_if(condition: Condition, rule: Box<dyn Rule>, changes: Vec<ConfigChange>) -> ConditionalRule {
+        ConditionalRule::new(condition, rule, ConditionalMode::Modify(changes))
+    }
+
+    /// Save rule of an Anti-X weapon, improving its rend against targets with the keyword.
+    pub fn anti(keyword: &str, rend: i32) -> ConditionalRule {
+        ConditionalRule::modified_if(Condition::TargetKeyword(keyword.to_string()), Box::new(SaveRule), vec![ConfigChange::Rend(rend)])
+    }
+
+    /// Configuration the wrapped rule is applied with, `None` if it is skipped.
+    fn effective_config(&self, config: &CombatConfig) -> Option<CombatConfig> {
+        let holds = self.condition.holds(&config.context);
+        match &self.mode {
+            ConditionalMode::Apply if holds => Some(config.clone()),
+            ConditionalMode::Apply => None,
+            ConditionalMode::Modify(changes) if holds => Some(changes.iter().fold(config.clone(), |config, change| change.apply(&config))),
+            ConditionalMode::Modify(_) => Some(config.clone()),
+        }
+    }
+}
+
+impl Rule for ConditionalRule {
+    fn apply(&self, status: &CombatStatus, config: &CombatConfig) -> Distribution<CombatStatus> {
+        match self.effective_config(config) {
+            Some(config) => self.rule.apply(status, &config),
+            None => Distribution::certain(*status),
+        }
+    }
+
+    fn sample(&self, status: &CombatStatus, config: &CombatConfig, rng: &mut dyn RngCore) -> CombatStatus {
+        match self.effective_config(config) {
+            Some(config) => self.rule.sample(status, &config, rng),
+            None => *status,
+        }
+    }
+
+    fn stage(&self) -> Option<RuleStage> {
+        self.rule.stage()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probabilities::combat_stats::{AttackStats, Characteristic, DefenseStats, RollModifier, TargetProfile};
+    use crate::probabilities::combat_tree::{SequenceBuilder, compute_damages};
+    use crate::probabilities::rules::{AttackCharacteristicRule, DamagesRule, HitRule, WardRule, WoundRule};
+
+    fn config(rend: i32, modifier: RollModifier) -> CombatConfig {
+        let attack_stats = AttackStats::new(Characteristic::Value(4), 3, 4, rend, Characteristic::Value(1));
+        CombatConfig::new_with_modifiers(attack_stats, DefenseStats::new(4, Some(5)), modifier)
+    }
+
+    fn sequence(hit: Box<dyn Rule>, save: Box<dyn Rule>, ward: Box<dyn Rule>) -> Vec<Box<dyn Rule>> {
+        vec![Box::new(AttackCharacteristicRule), hit, Box::new(WoundRule), save, Box::new(DamagesRule), ward]
+    }
+
+    #[test]
+    fn conditions_follow_the_context() {
+        let context = CombatContext::new().with_flag("charged").with_target_keyword("Monster");
+        assert!(Condition::Flag("charged".to_string()).holds(&context));
+        assert!(Condition::TargetKeyword("MONSTER".to_string()).holds(&context));
+        assert!(!Condition::AttackerKeyword("MONSTER".to_string()).holds(&context));
+        assert!(!Condition::TargetModelsAtLeast(1).holds(&context));
+        assert!(Condition::TargetModelsAtLeast(5).holds(&context.with_target_models(5)));
+        let charged = Condition::Flag("charged".to_string());
+        let hero = Condition::TargetKeyword("HERO".to_string());
+        assert!(Condition::Not(Box::new(hero.clone())).holds(&context));
+        assert!(!Condition::All(vec![charged.clone(), hero.clone()]).holds(&context));
+        assert!(Condition::Any(vec![charged, hero]).holds(&context));
+    }
+
+    #[test]
+    fn modified_rules_apply_their_changes_when_the_condition_holds() {
+        let charged_hit = || -> Box<dyn Rule> {
+            Box::new(ConditionalRule::modified_if(Condition::Flag("charged".to_string()), Box::new(HitRule), vec![ConfigChange::HitModifier(1)]))
+        };
+        let damages = |config: CombatConfig, hit: Box<dyn Rule>| {
+            compute_damages(config, &sequence(hit, Box::new(SaveRule), Box::new(WardRule))).values_and_probas()
+        };
+        let charged = config(0, RollModifier::new_null()).with_context(CombatContext::new().with_flag("charged"));
+        assert_eq!(damages(charged, charged_hit()), damages(config(0, RollModifier::new(1, 0, 0)), Box::new(HitRule)));
+        assert_eq!(damages(config(0, RollModifier::new_null()), charged_hit()), damages(config(0, RollModifier::new_null()), Box::new(HitRule)));
+    }
+
+    #[test]
+    fn enabled_rules_are_skipped_when_the_condition_fails() {
+        let not_monster = Condition::Not(Box::new(Condition::TargetKeyword("MONSTER".to_string())));
+        let damages = |context: CombatContext, ward: bool| {
+            let ward_rule = ConditionalRule::enabled_if(not_monster.clone(), Box::new(WardRule));
+            let rules = sequence(Box::new(HitRule), Box::new(SaveRule), Box::new(ward_rule));
+            let config = config(0, RollModifier::new_null()).with_context(context);
+            let expected = compute_damages(config.clone(), &SequenceBuilder::new().with_ward(ward).build());
+            (compute_damages(config, &rules).values_and_probas(), expected.values_and_probas())
+        };
+        let (warded, expected) = damages(CombatContext::new(), true);
+        assert_eq!(warded, expected);
+        let (unwarded, expected) = damages(CombatContext::new().with_target_keyword("monster"), false);
+        assert_eq!(unwarded, expected);
+    }
+
+    #[test]
+    fn anti_rules_improve_rend_against_the_keyword() {
+        let damages = |config: CombatConfig, save: Box<dyn Rule>| {
+            compute_damages(config, &sequence(Box::new(HitRule), save, Box::new(WardRule))).values_and_probas()
+        };
+        let target = |keyword: &str| TargetProfile::new(DefenseStats::new(4, Some(5)), 10, 1).with_keyword(keyword);
+        let base = config(1, RollModifier::new_null());
+        assert_eq!(
+            damages(base.against(&target("Monster")), Box::new(ConditionalRule::anti("MONSTER", 1))),
+            damages(config(2, RollModifier::new_null()), Box::new(SaveRule)),
+        );
+        assert_eq!(
+            damages(base.against(&target("Infantry")), Box::new(ConditionalRule::anti("MONSTER", 1))),
+            damages(base, Box::new(SaveRule)),
+        );
+    }
+}
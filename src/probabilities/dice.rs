@@ -1,8 +1,8 @@
-use std::collections::HashMap;
-use regex::Regex;
 use std::fmt;
+use crate::probabilities::dice_expression::DiceExpression;
+use crate::probabilities::distribution::Distribution;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum DiceRoll {
     D6,
     D3,
@@ -12,117 +12,139 @@ pub enum DiceRoll {
     D3Plus(u32),
     ND3Plus(u32, u32),
     ND6Plus(u32, u32),
+    Expression(DiceExpression),
 }
 
 impl DiceRoll {
-    pub fn values_and_probas(&self) -> Vec<(u32, f64)> {
+    pub fn distribution(&self) -> Distribution<u32> {
         match self {
-            DiceRoll::D6 => (1..=6).map(|x| (x, 1.0 / 6.0)).collect(),
-            DiceRoll::D3 => (1..=3).map(|x| (x, 1.0 / 3.0)).collect(),
-            DiceRoll::D6Plus(n) => (1..=6).map(|x| (x + n, 1.0 / 6.0)).collect(),
-            DiceRoll::D3Plus(n) => (1..=3).map(|x| (x + n, 1.0 / 3.0)).collect(),
-            DiceRoll::ND6(n) => _generate_dice_rolls(*n as usize, 6),
-            DiceRoll::ND3(n) => _generate_dice_rolls(*n as usize, 3),
-            DiceRoll::ND3Plus(n, m) => _generate_dice_rolls(*n as usize, 3)
-                .iter()
-                .map(|(x, proba)| (*x + m, *proba))
-                .collect(),
-            DiceRoll::ND6Plus(n, m) => _generate_dice_rolls(*n as usize, 6)
-                .iter()
-                .map(|(x, proba)| (*x + m, *proba))
-                .collect(),
+            DiceRoll::D6 => _dice_distribution(1, 6),
+            DiceRoll::D3 => _dice_distribution(1, 3),
+            DiceRoll::D6Plus(n) => _dice_distribution(1, 6).map(|x| x + n),
+            DiceRoll::D3Plus(n) => _dice_distribution(1, 3).map(|x| x + n),
+            DiceRoll::ND6(n) => _dice_distribution(*n, 6),
+            DiceRoll::ND3(n) => _dice_distribution(*n, 3),
+            DiceRoll::ND3Plus(n, m) => _dice_distribution(*n, 3).map(|x| x + m),
+            DiceRoll::ND6Plus(n, m) => _dice_distribution(*n, 6).map(|x| x + m),
+            DiceRoll::Expression(expression) => expression.distribution(),
         }
     }
-}
-
-fn _generate_dice_rolls(n_dices: usize, n_faces: u32) -> Vec<(u32, f64)> {
-    let mut rolls = Vec::new();
-    _generate_dice_rolls_recursive(n_dices, n_faces, 0, 0, &mut rolls);
-
-    let roll_counts: HashMap<u32, u32> = rolls.iter().fold(HashMap::new(), |mut acc, roll| {
-        *acc.entry(*roll).or_insert(0) += 1;
-        acc
-    });
 
-    let proba_n_dice_rolls = 1.0 / (n_faces.pow(n_dices as u32) as f64);
-    roll_counts
-        .iter()
-        .map(|(roll, count)| (*roll, *count as f64 * proba_n_dice_rolls))
-        .collect()
+    pub fn values_and_probas(&self) -> Vec<(u32, f64)> {
+        self.distribution().values_and_probas()
+    }
 }
 
-fn _generate_dice_rolls_recursive(
-    n_dices: usize,
-    n_faces: u32,
-    current_dice_index: usize,
-    current_roll: u32,
-    rolls: &mut Vec<u32>,
-) {
-    if current_dice_index == n_dices {
-        rolls.push(current_roll);
-        return;
-    }
+fn _dice_distribution(n_dices: u32, n_faces: u32) -> Distribution<u32> {
+    Distribution::uniform((1..=n_faces).collect()).sum_of(n_dices, 0)
+}
 
-    for i in 1..=n_faces {
-        _generate_dice_rolls_recursive(
-            n_dices,
-            n_faces,
-            current_dice_index + 1,
-            current_roll + i,
-            rolls,
-        );
+impl fmt::Display for DiceRoll {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiceRoll::D6 => write!(f, "D6"),
+            DiceRoll::D3 => write!(f, "D3"),
+            DiceRoll::ND6(n) => write!(f, "{}D6", n),
+            DiceRoll::ND3(n) => write!(f, "{}D3", n),
+            DiceRoll::D6Plus(m) => write!(f, "D6+{}", m),
+            DiceRoll::D3Plus(m) => write!(f, "D3+{}", m),
+            DiceRoll::ND3Plus(n, m) => write!(f, "{}D3+{}", n, m),
+            DiceRoll::ND6Plus(n, m) => write!(f, "{}D6+{}", n, m),
+            DiceRoll::Expression(expression) => write!(f, "{}", expression),
+        }
     }
 }
 
 impl DiceRoll {
     pub fn from_str(dice_str: String) -> Result<DiceRoll, DiceRollParseError> {
-        let re = Regex::new(r"(?<n>\d+)?D(?<faces>[36])(\+(?<bonus>\d+))?").map_err(|_| DiceRollParseError::InvalidRegex)?;
-        
-        if let Some(captures) = re.captures(&dice_str) {
-            let n = captures.name("n").map_or(1, |m| m.as_str().parse().unwrap());
-            let faces = captures.name("faces").map(|m| m.as_str().parse().unwrap()).unwrap();
-            let bonus = captures.name("bonus").map_or(0, |m| m.as_str().parse().unwrap());
+        DiceExpression::parse(&dice_str).map(DiceRoll::from)
+    }
+}
 
-            if n == 1 {
-                match faces {
-                    3 => match bonus {
-                        0 => Ok(DiceRoll::D3),
-                        _ => Ok(DiceRoll::D3Plus(bonus)),
-                    },
-                    6 => match bonus {
-                        0 => Ok(DiceRoll::D6),
-                        _ => Ok(DiceRoll::D6Plus(bonus)),
-                    },
-                    _ => Err(DiceRollParseError::InvalidFaceNumber),
-                }
-            } else {
-                match faces {
-                    3 => match bonus {
-                        0 => Ok(DiceRoll::ND3(n)),
-                        _ => Ok(DiceRoll::ND3Plus(n, bonus)),
-                    },
-                    6 => match bonus {
-                        0 => Ok(DiceRoll::ND6(n)),
-                        _ => Ok(DiceRoll::ND6Plus(n, bonus)),
-                    },
-                    _ => Err(DiceRollParseError::InvalidFaceNumber),
-                }
-            }
-        } else {
-            Err(DiceRollParseError::InvalidFormat)
+impl From<DiceExpression> for DiceRoll {
+    // Simple expressions are mapped to the dedicated variants
+    fn from(expression: DiceExpression) -> DiceRoll {
+        match &expression {
+            DiceExpression::Dice(1, 3) => DiceRoll::D3,
+            DiceExpression::Dice(1, 6) => DiceRoll::D6,
+            DiceExpression::Dice(n, 3) if *n > 1 => DiceRoll::ND3(*n),
+            DiceExpression::Dice(n, 6) if *n > 1 => DiceRoll::ND6(*n),
+            DiceExpression::Sum(dice, bonus) => match (dice.as_ref(), bonus.as_ref()) {
+                (DiceExpression::Dice(1, 3), DiceExpression::Constant(m)) if *m > 0 => DiceRoll::D3Plus(*m as u32),
+                (DiceExpression::Dice(1, 6), DiceExpression::Constant(m)) if *m > 0 => DiceRoll::D6Plus(*m as u32),
+                (DiceExpression::Dice(n, 3), DiceExpression::Constant(m)) if *n > 1 && *m > 0 => DiceRoll::ND3Plus(*n, *m as u32),
+                (DiceExpression::Dice(n, 6), DiceExpression::Constant(m)) if *n > 1 && *m > 0 => DiceRoll::ND6Plus(*n, *m as u32),
+                _ => DiceRoll::Expression(expression),
+            },
+            _ => DiceRoll::Expression(expression),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// Error raised when parsing a dice expression, positions are character offsets in the input.
+#[derive(Debug, Clone, PartialEq)]
 pub enum DiceRollParseError {
-    InvalidRegex,
-    InvalidFaceNumber,
-    InvalidFormat
+    UnexpectedCharacter { position: usize, character: char },
+    UnexpectedEnd { position: usize },
+    InvalidNumber { position: usize },
+    /// The dice have no faces or more than `MAX_DICE_FACES` faces.
+    InvalidFaceNumber { position: usize, faces: u32 },
+    /// More than `MAX_DICE_COUNT` dice are rolled at once.
+    TooManyDice { position: usize, count: u32 },
+    UnknownFunction { position: usize, name: String },
+    NonConstantProduct { position: usize },
+    /// The values of the expression do not fit in an integer.
+    Overflow { position: usize },
+    /// The expression starting at `position` may be negative.
+    NegativeValue { position: usize },
+}
+
+impl DiceRollParseError {
+    pub fn position(&self) -> usize {
+        match self {
+            DiceRollParseError::UnexpectedCharacter { position, .. }
+            | DiceRollParseError::UnexpectedEnd { position }
+            | DiceRollParseError::InvalidNumber { position }
+            | DiceRollParseError::InvalidFaceNumber { position, .. }
+            | DiceRollParseError::TooManyDice { position, .. }
+            | DiceRollParseError::UnknownFunction { position, .. }
+            | DiceRollParseError::NonConstantProduct { position }
+            | DiceRollParseError::Overflow { position }
+            | DiceRollParseError::NegativeValue { position } => *position,
+        }
+    }
 }
 
 impl fmt::Display for DiceRollParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            DiceRollParseError::UnexpectedCharacter { position, character } => {
+                write!(f, "unexpected character '{}' at position {}", character, position)
+            }
+            DiceRollParseError::UnexpectedEnd { position } => {
+                write!(f, "unexpected end of expression at position {}", position)
+            }
+            DiceRollParseError::InvalidNumber { position } => {
+                write!(f, "invalid number at position {}", position)
+            }
+            DiceRollParseError::InvalidFaceNumber { position, faces } => {
+                write!(f, "invalid number of faces {} at position {}", faces, position)
+            }
+            DiceRollParseError::TooManyDice { position, count } => {
+                write!(f, "too many dice {} at position {}", count, position)
+            }
+            DiceRollParseError::UnknownFunction { position, name } => {
+                write!(f, "unknown function '{}' at position {}", name, position)
+            }
+            DiceRollParseError::NonConstantProduct { position } => {
+                write!(f, "multiplication at position {} requires a constant operand", position)
+            }
+            DiceRollParseError::Overflow { position } => {
+                write!(f, "value of the expression overflows at position {}", position)
+            }
+            DiceRollParseError::NegativeValue { position } => {
+                write!(f, "expression at position {} may be negative, use max(..., 0)", position)
+            }
+        }
     }
-}
\ No newline at end of file
+}
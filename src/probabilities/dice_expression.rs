@@ -0,0 +1,474 @@
+use std::fmt;
+
+use crate::probabilities::dice::DiceRollParseError;
+use crate::probabilities::distribution::Distribution;
+
+/// Largest number of faces of the dice of an expression.
+pub const MAX_DICE_FACES: u32 = 100;
+/// Largest number of dice rolled at once, such as the `n` of `nD6`.
+pub const MAX_DICE_COUNT: u32 = 20;
+
+/// Arbitrary dice expression such as `2D6+D3-1`, `2*D3` or `max(D6, 3)`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiceExpression {
+    Constant(i64),
+    /// `count` dice with `faces` faces each.
+    Dice(u32, u32),
+    Sum(Box<DiceExpression>, Box<DiceExpression>),
+    Difference(Box<DiceExpression>, Box<DiceExpression>),
+    /// Multiplication of an expression by a constant factor.
+    Product(i64, Box<DiceExpression>),
+    Max(Vec<DiceExpression>),
+    Min(Vec<DiceExpression>),
+}
+
+impl DiceExpression {
+    /// Parses an expression whose values are all within `0..=u32::MAX`, use `max(..., 0)` for rolls which may be negative.
+    ///
+    /// Constant operands are folded, so that `D6+0` is parsed as `D6` and `2*3` as `6`.
+    pub fn parse(expression: &str) -> Result<DiceExpression, DiceRollParseError> {
+        let mut parser = Parser::new(expression);
+        parser.skip_whitespace();
+        let start = parser.position;
+        let parsed = parser.parse_expression()?;
+        parser.skip_whitespace();
+        if let Some(character) = parser.peek() {
+            return Err(DiceRollParseError::UnexpectedCharacter {
+                position: parser.position,
+                character,
+            });
+        }
+        match parsed.bounds() {
+            Some((min, _)) if min < 0 => Err(DiceRollParseError::NegativeValue { position: start }),
+            Some((_, max)) if max <= u32::MAX as i64 => Ok(parsed),
+            _ => Err(DiceRollParseError::Overflow { position: start }),
+        }
+    }
+
+    /// Value of the expression if it does not involve any dice.
+    pub fn constant_value(&self) -> Option<i64> {
+        match self {
+            DiceExpression::Constant(value) => Some(*value),
+            DiceExpression::Dice(count, _) if *count == 0 => Some(0),
+            DiceExpression::Dice(_, _) => None,
+            DiceExpression::Sum(lhs, rhs) => lhs.constant_value()?.checked_add(rhs.constant_value()?),
+            DiceExpression::Difference(lhs, rhs) => lhs.constant_value()?.checked_sub(rhs.constant_value()?),
+            DiceExpression::Product(factor, expr) => factor.checked_mul(expr.constant_value()?),
+            DiceExpression::Max(exprs) => exprs
+                .iter()
+                .map(|expr| expr.constant_value())
+                .collect::<Option<Vec<i64>>>()?
+                .into_iter()
+                .max(),
+            DiceExpression::Min(exprs) => exprs
+                .iter()
+                .map(|expr| expr.constant_value())
+                .collect::<Option<Vec<i64>>>()?
+                .into_iter()
+                .min(),
+        }
+    }
+
+    /// Smallest and largest values of the expression, `None` if they do not fit in an `i64`.
+    fn bounds(&self) -> Option<(i64, i64)> {
+        match self {
+            DiceExpression::Constant(value) => Some((*value, *value)),
+            DiceExpression::Dice(count, faces) => Some((*count as i64, (*count as i64).checked_mul(*faces as i64)?)),
+            DiceExpression::Sum(lhs, rhs) => {
+                let ((lhs_min, lhs_max), (rhs_min, rhs_max)) = (lhs.bounds()?, rhs.bounds()?);
+                Some((lhs_min.checked_add(rhs_min)?, lhs_max.checked_add(rhs_max)?))
+            }
+            DiceExpression::Difference(lhs, rhs) => {
+                let ((lhs_min, lhs_max), (rhs_min, rhs_max)) = (lhs.bounds()?, rhs.bounds()?);
+                Some((lhs_min.checked_sub(rhs_max)?, lhs_max.checked_sub(rhs_min)?))
+            }
+            DiceExpression::Product(factor, expr) => {
+                let (min, max) = expr.bounds()?;
+                let (min, max) = (factor.checked_mul(min)?, factor.checked_mul(max)?);
+                Some((min.min(max), min.max(max)))
+            }
+            DiceExpression::Max(exprs) => exprs.iter().map(DiceExpression::bounds).try_fold(
+                (i64::MIN, i64::MIN),
+                |(min, max), bounds| bounds.map(|(lhs, rhs)| (min.max(lhs), max.max(rhs))),
+            ),
+            DiceExpression::Min(exprs) => exprs.iter().map(DiceExpression::bounds).try_fold(
+                (i64::MAX, i64::MAX),
+                |(min, max), bounds| bounds.map(|(lhs, rhs)| (min.min(lhs), max.min(rhs))),
+            ),
+        }
+    }
+
+    /// Distribution of the expression, which may take negative values.
+    pub fn signed_distribution(&self) -> Distribution<i64> {
+        match self {
+            DiceExpression::Constant(value) => Distribution::certain(*value),
+            DiceExpression::Dice(count, faces) => {
+                Distribution::uniform((1..=*faces as i64).collect()).sum_of(*count, 0)
+            }
+            DiceExpression::Sum(lhs, rhs) => lhs.signed_distribution() + rhs.signed_distribution(),
+            DiceExpression::Difference(lhs, rhs) => lhs
+                .signed_distribution()
+                .combine(&rhs.signed_distribution(), |a, b| a - b),
+            DiceExpression::Product(factor, expr) => expr.signed_distribution().scale(*factor),
+            DiceExpression::Max(exprs) => _fold(exprs, Distribution::max),
+            DiceExpression::Min(exprs) => _fold(exprs, Distribution::min),
+        }
+    }
+
+    /// Distribution of the expression, negative results being clamped to 0.
+    ///
+    /// Parsed expressions are never negative, only expressions built by hand can be clamped.
+    pub fn distribution(&self) -> Distribution<u32> {
+        self.signed_distribution().map(|value| (*value).max(0) as u32)
+    }
+
+    pub fn values_and_probas(&self) -> Vec<(u32, f64)> {
+        self.distribution().values_and_probas()
+    }
+}
+
+// Written back in the syntax accepted by `DiceExpression::parse`
+impl fmt::Display for DiceExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiceExpression::Constant(value) => write!(f, "{}", value),
+            DiceExpression::Dice(1, faces) => write!(f, "D{}", faces),
+            DiceExpression::Dice(count, faces) => write!(f, "{}D{}", count, faces),
+            DiceExpression::Sum(lhs, rhs) => write!(f, "{}+{}", lhs, Operand(rhs)),
+            DiceExpression::Difference(lhs, rhs) => write!(f, "{}-{}", lhs, Operand(rhs)),
+            DiceExpression::Product(factor, expr) => write!(f, "{}*{}", factor, Operand(expr)),
+            DiceExpression::Max(exprs) => write!(f, "max({})", _join(exprs)),
+            DiceExpression::Min(exprs) => write!(f, "min({})", _join(exprs)),
+        }
+    }
+}
+
+// Right operand of an operator, parenthesized when it is itself a sum or a difference
+struct Operand<'a>(&'a DiceExpression);
+
+impl fmt::Display for Operand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            DiceExpression::Sum(_, _) | DiceExpression::Difference(_, _) => write!(f, "({})", self.0),
+            expr => write!(f, "{}", expr),
+        }
+    }
+}
+
+fn _join(exprs: &[DiceExpression]) -> String {
+    exprs.iter().map(|expr| expr.to_string()).collect::<Vec<String>>().join(", ")
+}
+
+fn _fold(
+    exprs: &[DiceExpression],
+    op: fn(&Distribution<i64>, &Distribution<i64>) -> Distribution<i64>,
+) -> Distribution<i64> {
+    let mut distributions = exprs.iter().map(|expr| expr.signed_distribution());
+    let first = distributions.next().unwrap_or_else(|| Distribution::certain(0));
+    distributions.fold(first, |acc, distribution| op(&acc, &distribution))
+}
+
+// Rejects the expression built by the operator at `position` if its values overflow
+fn checked(expr: &DiceExpression, position: usize) -> Result<(), DiceRollParseError> {
+    match expr.bounds() {
+        Some(_) => Ok(()),
+        None => Err(DiceRollParseError::Overflow { position }),
+    }
+}
+
+// Folds the constant operands of a checked expression, adding or multiplying by a neutral constant
+// leaving the other operand as is
+fn folded(expr: DiceExpression) -> DiceExpression {
+    if let Some(value) = expr.constant_value() {
+        return DiceExpression::Constant(value);
+    }
+    match expr {
+        DiceExpression::Sum(lhs, rhs) if lhs.constant_value() == Some(0) => *rhs,
+        DiceExpression::Sum(lhs, rhs) | DiceExpression::Difference(lhs, rhs) if rhs.constant_value() == Some(0) => *lhs,
+        DiceExpression::Product(1, expr) => *expr,
+        expr => expr,
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn new(expression: &str) -> Parser {
+        Parser {
+            chars: expression.chars().collect(),
+            position: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), DiceRollParseError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(character) if character == expected => {
+                self.position += 1;
+                Ok(())
+            }
+            Some(character) => Err(DiceRollParseError::UnexpectedCharacter {
+                position: self.position,
+                character,
+            }),
+            None => Err(DiceRollParseError::UnexpectedEnd { position: self.position }),
+        }
+    }
+
+    // expression := term (('+' | '-') term)*
+    fn parse_expression(&mut self) -> Result<DiceExpression, DiceRollParseError> {
+        let mut expr = self.parse_term()?;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('+') => {
+                    let operator_position = self.position;
+                    self.position += 1;
+                    expr = DiceExpression::Sum(Box::new(expr), Box::new(self.parse_term()?));
+                    checked(&expr, operator_position)?;
+                    expr = folded(expr);
+                }
+                Some('-') => {
+                    let operator_position = self.position;
+                    self.position += 1;
+                    expr = DiceExpression::Difference(Box::new(expr), Box::new(self.parse_term()?));
+                    checked(&expr, operator_position)?;
+                    expr = folded(expr);
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    // term := factor ('*' factor)*
+    fn parse_term(&mut self) -> Result<DiceExpression, DiceRollParseError> {
+        let mut expr = self.parse_factor()?;
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('*') {
+                return Ok(expr);
+            }
+            let operator_position = self.position;
+            self.position += 1;
+            let rhs = self.parse_factor()?;
+            expr = match (expr.constant_value(), rhs.constant_value()) {
+                (Some(factor), _) => DiceExpression::Product(factor, Box::new(rhs)),
+                (None, Some(factor)) => DiceExpression::Product(factor, Box::new(expr)),
+                (None, None) => {
+                    return Err(DiceRollParseError::NonConstantProduct { position: operator_position })
+                }
+            };
+            checked(&expr, operator_position)?;
+            expr = folded(expr);
+        }
+    }
+
+    // factor := '-' factor | '(' expression ')' | number | number? 'D' number | function
+    fn parse_factor(&mut self) -> Result<DiceExpression, DiceRollParseError> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => Err(DiceRollParseError::UnexpectedEnd { position: self.position }),
+            Some('-') => {
+                self.position += 1;
+                Ok(DiceExpression::Product(-1, Box::new(self.parse_factor()?)))
+            }
+            Some('(') => {
+                self.position += 1;
+                let expr = self.parse_expression()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let count_position = self.position;
+                let count = self.parse_number()?;
+                if matches!(self.peek(), Some('D') | Some('d')) {
+                    if count > MAX_DICE_COUNT {
+                        return Err(DiceRollParseError::TooManyDice { position: count_position, count });
+                    }
+                    self.position += 1;
+                    self.parse_dice(count)
+                } else {
+                    Ok(DiceExpression::Constant(count as i64))
+                }
+            }
+            Some('D') | Some('d') => {
+                self.position += 1;
+                self.parse_dice(1)
+            }
+            Some(c) if c.is_alphabetic() => self.parse_function(),
+            Some(character) => Err(DiceRollParseError::UnexpectedCharacter {
+                position: self.position,
+                character,
+            }),
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<u32, DiceRollParseError> {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.position += 1;
+        }
+        if start == self.position {
+            return match self.peek() {
+                Some(character) => Err(DiceRollParseError::UnexpectedCharacter {
+                    position: self.position,
+                    character,
+                }),
+                None => Err(DiceRollParseError::UnexpectedEnd { position: self.position }),
+            };
+        }
+        self.chars[start..self.position]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .map_err(|_| DiceRollParseError::InvalidNumber { position: start })
+    }
+
+    fn parse_dice(&mut self, count: u32) -> Result<DiceExpression, DiceRollParseError> {
+        let faces_position = self.position;
+        let faces = self.parse_number()?;
+        if faces == 0 || faces > MAX_DICE_FACES {
+            return Err(DiceRollParseError::InvalidFaceNumber { position: faces_position, faces });
+        }
+        let dice = DiceExpression::Dice(count, faces);
+        checked(&dice, faces_position)?;
+        Ok(dice)
+    }
+
+    // function := ('max' | 'min') '(' expression (',' expression)* ')'
+    fn parse_function(&mut self) -> Result<DiceExpression, DiceRollParseError> {
+        let start = self.position;
+        while self.peek().is_some_and(char::is_alphabetic) {
+            self.position += 1;
+        }
+        let name: String = self.chars[start..self.position].iter().collect();
+        let is_max = match name.to_lowercase().as_str() {
+            "max" => true,
+            "min" => false,
+            _ => return Err(DiceRollParseError::UnknownFunction { position: start, name }),
+        };
+
+        self.expect('(')?;
+        let mut arguments = vec![self.parse_expression()?];
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.position += 1;
+                    arguments.push(self.parse_expression()?);
+                }
+                _ => break,
+            }
+        }
+        self.expect(')')?;
+
+        if is_max {
+            Ok(folded(DiceExpression::Max(arguments)))
+        } else {
+            Ok(folded(DiceExpression::Min(arguments)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(expression: &str) -> DiceRollParseError {
+        DiceExpression::parse(expression).expect_err("expected an invalid expression")
+    }
+
+    #[test]
+    fn errors_give_their_position() {
+        assert_eq!(error("2D6+x"), DiceRollParseError::UnknownFunction { position: 4, name: "x".to_string() });
+        assert_eq!(error("2D6 +"), DiceRollParseError::UnexpectedEnd { position: 5 });
+        assert_eq!(error("D0"), DiceRollParseError::InvalidFaceNumber { position: 1, faces: 0 });
+        assert_eq!(error("D6*D3"), DiceRollParseError::NonConstantProduct { position: 2 });
+        assert_eq!(error("max(D6; 3)"), DiceRollParseError::UnexpectedCharacter { position: 6, character: ';' });
+        assert_eq!(error("99999999999"), DiceRollParseError::InvalidNumber { position: 0 });
+    }
+
+    #[test]
+    fn dice_are_capped() {
+        assert_eq!(error("D4000000000"), DiceRollParseError::InvalidFaceNumber { position: 1, faces: 4000000000 });
+        assert_eq!(error("2D101"), DiceRollParseError::InvalidFaceNumber { position: 2, faces: 101 });
+        assert_eq!(error("D6+4000000000D6"), DiceRollParseError::TooManyDice { position: 3, count: 4000000000 });
+        assert_eq!(error("21D6"), DiceRollParseError::TooManyDice { position: 0, count: 21 });
+        let largest = DiceExpression::parse("20D100").unwrap();
+        assert_eq!(largest.distribution().len(), 1981);
+    }
+
+    #[test]
+    fn overflows_are_rejected() {
+        assert_eq!(error("4000000000*4000000000*4000000000"), DiceRollParseError::Overflow { position: 10 });
+        assert_eq!(error("20D100*4000000000*4000000000"), DiceRollParseError::Overflow { position: 17 });
+        // Values fitting in an i64 but not in a u32
+        assert_eq!(error(" 4294967295+D6"), DiceRollParseError::Overflow { position: 1 });
+        assert_eq!(DiceExpression::parse("4000000000+294967295").unwrap().constant_value(), Some(u32::MAX as i64));
+    }
+
+    #[test]
+    fn negative_values_are_rejected() {
+        assert_eq!(error("-D3"), DiceRollParseError::NegativeValue { position: 0 });
+        assert_eq!(error(" D6-2"), DiceRollParseError::NegativeValue { position: 1 });
+        let expression = DiceExpression::parse("max(D6-2, 0)").unwrap();
+        let (value, proba) = expression.distribution().values_and_probas()[0];
+        assert_eq!(value, 0);
+        assert!((proba - 1.0 / 3.0).abs() < 1e-12);
+    }
+
+    // Distribution of the expression, checked against the one computed by hand
+    fn assert_distribution(expression: &str, expected: &Distribution<u32>) {
+        let distribution = DiceExpression::parse(expression).unwrap().distribution();
+        assert_eq!(distribution.len(), expected.len(), "{}", expression);
+        for (value, proba) in expected.iter() {
+            assert!((distribution.probability(value) - proba).abs() < 1e-12, "{}: {}", expression, value);
+        }
+    }
+
+    fn by_hand(values: impl Iterator<Item = u32>, outcomes: u32) -> Distribution<u32> {
+        let mut counts = std::collections::BTreeMap::new();
+        for value in values {
+            *counts.entry(value).or_insert(0) += 1;
+        }
+        Distribution::new(counts.into_iter().map(|(value, count)| (value, count as f64 / outcomes as f64)).collect())
+    }
+
+    #[test]
+    fn distributions_follow_the_expressions() {
+        let d3 = || 1..=3u32;
+        let d6 = || 1..=6u32;
+        let two_d6_d3 = d6().flat_map(|a| d6().flat_map(move |b| d3().map(move |c| a + b + c - 1)));
+        assert_distribution("2D6+D3-1", &by_hand(two_d6_d3, 108));
+        assert_distribution("2*D3", &by_hand(d3().map(|value| 2 * value), 3));
+        assert_distribution("D3*2", &by_hand(d3().map(|value| 2 * value), 3));
+        assert_distribution("D20", &by_hand(1..=20, 20));
+        let nested = d6().flat_map(|a| d3().map(move |b| a.max(2 * (b + 1)).min(5)));
+        assert_distribution("min(max(D6, 2*(D3+1)), 5)", &by_hand(nested, 18));
+        let difference = d6().flat_map(|a| d3().map(move |b| (a as i64 - b as i64).max(0) as u32 + 1));
+        assert_distribution("max(D6-D3, 0)+1", &by_hand(difference, 18));
+    }
+
+    #[test]
+    fn constants_are_folded() {
+        assert_eq!(DiceExpression::parse("D6+0").unwrap(), DiceExpression::Dice(1, 6));
+        assert_eq!(DiceExpression::parse("0+2D6-0").unwrap(), DiceExpression::Dice(2, 6));
+        assert_eq!(DiceExpression::parse("1*D3").unwrap(), DiceExpression::Dice(1, 3));
+        assert_eq!(DiceExpression::parse("2*3+1").unwrap(), DiceExpression::Constant(7));
+        assert_eq!(DiceExpression::parse("max(2, 3)").unwrap(), DiceExpression::Constant(3));
+        assert_eq!(DiceExpression::parse("D6+2-1").unwrap().to_string(), "D6+2-1");
+    }
+}
@@ -0,0 +1,262 @@
+use rand::{Rng, RngCore};
+use std::collections::BTreeMap;
+use std::ops::{Add, Mul};
+
+/// Discrete probability distribution, stored as values sorted in increasing order
+/// with their probabilities. Values appearing several times are merged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Distribution<T> {
+    values_and_probas: Vec<(T, f64)>,
+}
+
+impl<T: Ord + Clone> Distribution<T> {
+    pub fn new(values_and_probas: Vec<(T, f64)>) -> Distribution<T> {
+        let mut grouped = BTreeMap::new();
+        for (value, proba) in values_and_probas {
+            *grouped.entry(value).or_insert(0.0) += proba;
+        }
+        Distribution {
+            values_and_probas: grouped.into_iter().collect(),
+        }
+    }
+
+    /// Distribution of a value known with certainty.
+    pub fn certain(value: T) -> Distribution<T> {
+        Distribution {
+            values_and_probas: vec![(value, 1.0)],
+        }
+    }
+
+    /// Values equally likely, no values giving an empty distribution.
+    pub fn uniform(values: Vec<T>) -> Distribution<T> {
+        if values.is_empty() {
+            return Distribution::new(Vec::new());
+        }
+        let proba = 1.0 / values.len() as f64;
+        Distribution::new(values.into_iter().map(|value| (value, proba)).collect())
+    }
+
+    pub fn values_and_probas(&self) -> Vec<(T, f64)> {
+        self.values_and_probas.clone()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, (T, f64)> {
+        self.values_and_probas.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values_and_probas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values_and_probas.is_empty()
+    }
+
+    pub fn probability(&self, value: &T) -> f64 {
+        self.values_and_probas
+            .binary_search_by(|(other, _)| other.cmp(value))
+            .map_or(0.0, |index| self.values_and_probas[index].1)
+    }
+
+    pub fn total_probability(&self) -> f64 {
+        self.values_and_probas.iter().map(|(_, proba)| proba).sum()
+    }
+
+    /// Distribution of `f(X)`.
+    pub fn map<U: Ord + Clone>(&self, f: impl Fn(&T) -> U) -> Distribution<U> {
+        Distribution::new(
+            self.values_and_probas
+                .iter()
+                .map(|(value, proba)| (f(value), *proba))
+                .collect(),
+        )
+    }
+
+    /// Chains a random step depending on the value of `X`.
+    pub fn and_then<U: Ord + Clone>(&self, f: impl Fn(&T) -> Distribution<U>) -> Distribution<U> {
+        let mut values_and_probas = Vec::new();
+        for (value, proba) in &self.values_and_probas {
+            for (next_value, next_proba) in f(value).values_and_probas {
+                values_and_probas.push((next_value, proba * next_proba));
+            }
+        }
+        Distribution::new(values_and_probas)
+    }
+
+    /// Distribution of `f(X, Y)` for `X` and `Y` independent.
+    pub fn combine<U: Ord + Clone, V: Ord + Clone>(
+        &self,
+        other: &Distribution<U>,
+        f: impl Fn(&T, &U) -> V,
+    ) -> Distribution<V> {
+        let mut values_and_probas = Vec::with_capacity(self.len() * other.len());
+        for (value, proba) in &self.values_and_probas {
+            for (other_value, other_proba) in &other.values_and_probas {
+                values_and_probas.push((f(value, other_value), proba * other_proba));
+            }
+        }
+        Distribution::new(values_and_probas)
+    }
+
+    /// Value drawn at random, following the distribution, `None` if the distribution is empty.
+    pub fn sample(&self, rng: &mut dyn RngCore) -> Option<T> {
+        let mut threshold = rng.gen::<f64>() * self.total_probability();
+        for (value, proba) in &self.values_and_probas {
+            if threshold < *proba {
+                return Some(value.clone());
+            }
+            threshold -= proba;
+        }
+        self.values_and_probas.last().map(|(value, _)| value.clone())
+    }
+
+    /// Weighted mixture of distributions, weights are expected to sum to 1.
+    pub fn mixture(components: Vec<(Distribution<T>, f64)>) -> Distribution<T> {
+        Distribution::new(
+            components
+                .into_iter()
+                .flat_map(|(distribution, weight)| {
+                    distribution
+                        .values_and_probas
+                        .into_iter()
+                        .map(move |(value, proba)| (value, proba * weight))
+                })
+                .collect(),
+        )
+    }
+
+    /// Distribution of `X` knowing that `predicate(X)` holds, `None` if the event has a null probability.
+    pub fn condition(&self, predicate: impl Fn(&T) -> bool) -> Option<Distribution<T>> {
+        let kept: Vec<(T, f64)> = self
+            .values_and_probas
+            .iter()
+            .filter(|(value, _)| predicate(value))
+            .cloned()
+            .collect();
+        let total: f64 = kept.iter().map(|(_, proba)| proba).sum();
+        if total <= 0.0 {
+            return None;
+        }
+        Some(Distribution {
+            values_and_probas: kept.into_iter().map(|(value, proba)| (value, proba / total)).collect(),
+        })
+    }
+
+    /// Distribution of `max(X, Y)` for `X` and `Y` independent.
+    pub fn max(&self, other: &Distribution<T>) -> Distribution<T> {
+        self.combine(other, |a, b| a.max(b).clone())
+    }
+
+    /// Distribution of `min(X, Y)` for `X` and `Y` independent.
+    pub fn min(&self, other: &Distribution<T>) -> Distribution<T> {
+        self.combine(other, |a, b| a.min(b).clone())
+    }
+}
+
+impl Distribution<u32> {
+    /// Number of successes among `n` independent trials of probability `p`.
+    pub fn binomial(n: u32, p: f64) -> Distribution<u32> {
+        Distribution::new(vec![(0, 1.0 - p), (1, p)]).sum_of(n, 0)
+    }
+}
+
+impl<T: Ord + Clone + Add<Output = T>> Distribution<T> {
+    /// Distribution of the sum of `n` independent copies of `X`, `zero` being the empty sum.
+    pub fn sum_of(&self, n: u32, zero: T) -> Distribution<T> {
+        let mut result = Distribution::certain(zero);
+        let mut power = self.clone();
+        let mut remaining = n;
+        while remaining > 0 {
+            if remaining & 1 == 1 {
+                result = &result + &power;
+            }
+            remaining >>= 1;
+            if remaining > 0 {
+                power = &power + &power;
+            }
+        }
+        result
+    }
+}
+
+impl<T: Ord + Clone + Mul<Output = T>> Distribution<T> {
+    /// Distribution of `factor * X`.
+    pub fn scale(&self, factor: T) -> Distribution<T> {
+        self.map(|value| value.clone() * factor.clone())
+    }
+}
+
+/// Convolution: distribution of `X + Y` for `X` and `Y` independent.
+impl<T: Ord + Clone + Add<Output = T>> Add for &Distribution<T> {
+    type Output = Distribution<T>;
+    fn add(self, other: &Distribution<T>) -> Distribution<T> {
+        self.combine(other, |a, b| a.clone() + b.clone())
+    }
+}
+
+impl<T: Ord + Clone + Add<Output = T>> Add for Distribution<T> {
+    type Output = Distribution<T>;
+    fn add(self, other: Distribution<T>) -> Distribution<T> {
+        &self + &other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    fn d6() -> Distribution<u32> {
+        Distribution::uniform((1..=6).collect())
+    }
+
+    #[test]
+    fn values_are_merged_and_sorted() {
+        let distribution = Distribution::new(vec![(2, 0.25), (1, 0.5), (2, 0.25)]);
+        assert_eq!(distribution.values_and_probas(), vec![(1, 0.5), (2, 0.5)]);
+        assert_eq!(distribution.probability(&2), 0.5);
+        assert_eq!(distribution.probability(&3), 0.0);
+    }
+
+    #[test]
+    fn empty_distributions_are_handled() {
+        let empty: Distribution<u32> = Distribution::uniform(Vec::new());
+        assert!(empty.is_empty());
+        assert_eq!(empty.total_probability(), 0.0);
+        assert_eq!(empty.sample(&mut ChaCha8Rng::seed_from_u64(0)), None);
+    }
+
+    #[test]
+    fn sums_match_convolutions() {
+        let two_dice = d6().sum_of(2, 0);
+        assert_eq!(two_dice, &d6() + &d6());
+        assert_eq!(two_dice.len(), 11);
+        assert!((two_dice.probability(&7) - 1.0 / 6.0).abs() < 1e-12);
+        assert_eq!(d6().sum_of(0, 0), Distribution::certain(0));
+    }
+
+    #[test]
+    fn binomial_matches_the_closed_form() {
+        let binomial = Distribution::binomial(3, 0.5);
+        for (successes, expected) in [(0, 0.125), (1, 0.375), (2, 0.375), (3, 0.125)] {
+            assert!((binomial.probability(&successes) - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn conditioning_renormalizes() {
+        let high = d6().condition(|value| *value > 4).unwrap();
+        assert_eq!(high.values_and_probas(), vec![(5, 0.5), (6, 0.5)]);
+        assert!(d6().condition(|value| *value > 6).is_none());
+    }
+
+    #[test]
+    fn samples_follow_the_distribution() {
+        let distribution = Distribution::new(vec![(0, 0.2), (1, 0.8)]);
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let samples = 10000;
+        let ones = (0..samples).filter(|_| distribution.sample(&mut rng) == Some(1)).count();
+        assert!((ones as f64 / samples as f64 - 0.8).abs() < 0.02);
+    }
+}
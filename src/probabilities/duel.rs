@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+
+use crate::probabilities::combat_stats::{AttackStats, DefenseStats, Rerolls, RollModifier, TargetUnit};
+use crate::probabilities::combat_tree::{CombatConfig, CombatContext, Rule};
+use crate::probabilities::distribution::Distribution;
+use crate::probabilities::wound_allocation::{UnitHealth, WoundAllocation, sample_kills};
+use rand::RngCore;
+
+/// Unit fighting in a duel, both attacking and being attacked.
+///
+/// The unit attacks with its surviving models, whatever the model count of its attack stats.
+/// Its champion is assumed to be the last model removed, their extra attacks being kept while any model survives.
+/// The target models of its context are those of the attacked unit still alive.
+/// As with `compute_kills`, damage and wards are rolled wound by wound during the allocation.
+#[derive(Debug)]
+pub struct Combatant {
+    pub unit: TargetUnit,
+    pub attack_stats: AttackStats,
+    pub defense_stats: DefenseStats,
+    pub sequence: Vec<Box<dyn Rule>>,
+    pub modifier: RollModifier,
+    pub rerolls: Rerolls,
+    /// Flags and keywords checked by conditional rules.
+    pub context: CombatContext,
+    pub strike_first: bool,
+    pub strike_last: bool,
+}
+
+impl Combatant {
+    pub fn new(
+        unit: TargetUnit,
+        attack_stats: AttackStats,
+        defense_stats: DefenseStats,
+        sequence: Vec<Box<dyn Rule>>,
+    ) -> Combatant {
+        Combatant {
+            unit,
+            attack_stats,
+            defense_stats,
+            sequence,
+            modifier: RollModifier::new_null(),
+            rerolls: Rerolls::none(),
+            context: CombatContext::new(),
+            strike_first: false,
+            strike_last: false,
+        }
+    }
+
+    pub fn with_modifier(self, modifier: RollModifier) -> Combatant {
+        Combatant {modifier, ..self}
+    }
+
+    pub fn with_rerolls(self, rerolls: Rerolls) -> Combatant {
+        Combatant {rerolls, ..self}
+    }
+
+    pub fn with_context(self, context: CombatContext) -> Combatant {
+        Combatant {context, ..self}
+    }
+
+    pub fn with_strike_first(self, strike_first: bool) -> Combatant {
+        Combatant {strike_first, ..self}
+    }
+
+    pub fn with_strike_last(self, strike_last: bool) -> Combatant {
+        Combatant {strike_last, ..self}
+    }
+
+    // Strike first and strike last cancel each other out
+    fn priority(&self) -> i32 {
+        self.strike_first as i32 - self.strike_last as i32
+    }
+
+    fn config(&self, models: u32, target: &Combatant, target_health: UnitHealth) -> CombatConfig {
+        let attack_stats = match models {
+            0 => self.attack_stats.with_models(0).with_champion_attacks(0),
+            _ => self.attack_stats.with_models(models),
+        };
+        CombatConfig::new_with_modifiers(
+            attack_stats,
+            target.defense_stats.clone(),
+            self.modifier,
+        )
+        .with_rerolls(self.rerolls)
+        .with_context(self.context.with_target_models(target_health.models))
+    }
+
+    /// Health of `target` after being attacked by `models` models of the unit.
+    fn attack(&self, models: u32, target: &Combatant, target_health: UnitHealth) -> Distribution<UnitHealth> {
+        let allocation = WoundAllocation {target: target.unit, health: Distribution::certain(target_health)};
+        allocation.allocate_attack(self.config(models, target, target_health), &self.sequence).health
+    }
+
+    fn sample_attack(&self, models: u32, target: &Combatant, target_health: UnitHealth, rng: &mut dyn RngCore) -> UnitHealth {
+        let config = self.config(models, target, target_health);
+        sample_kills(&config, &self.sequence, target_health, target.unit.wounds_per_model, rng)
+    }
+}
+
+// Attacks already computed during a battle, keyed on whether the first unit attacks,
+// the number of attacking models and the health of the target
+pub(crate) type AttackCache = HashMap<(bool, u32, UnitHealth), Distribution<UnitHealth>>;
+
+/// Two units fighting each other, the second one striking back with its surviving models.
+///
+/// The first unit, usually the charging one, fights first unless the strike first and strike
+/// last effects of the units say otherwise.
+#[derive(Debug)]
+pub struct Duel {
+    pub first: Combatant,
+    pub second: Combatant,
+}
+
+impl Duel {
+    pub fn new(first: Combatant, second: Combatant) -> Duel {
+        Duel {first, second}
+    }
+
+    pub fn first_strikes_first(&self) -> bool {
+        self.first.priority() >= self.second.priority()
+    }
+
+    /// Health of both units before fighting.
+    pub fn initial_health(&self) -> (UnitHealth, UnitHealth) {
+        (UnitHealth::from_target(&self.first.unit), UnitHealth::from_target(&self.second.unit))
+    }
+
+    /// Health of both units after a round of combat starting from the given health.
+    pub fn round(&self, health: (UnitHealth, UnitHealth)) -> Distribution<(UnitHealth, UnitHealth)> {
+        self.cached_round(health, &mut HashMap::new())
+    }
+
+    pub(crate) fn cached_round(&self, health: (UnitHealth, UnitHealth), cache: &mut AttackCache) -> Distribution<(UnitHealth, UnitHealth)> {
+        let first_strikes = self.first_strikes_first();
+        let (striker, responder, striker_health, responder_health) = match first_strikes {
+            true => (&self.first, &self.second, health.0, health.1),
+            false => (&self.second, &self.first, health.1, health.0),
+        };
+        if striker_health.is_destroyed() || responder_health.is_destroyed() {
+            return Distribution::certain(health);
+        }
+
+        let mut attack = |attacker: &Combatant, target: &Combatant, key: (bool, u32, UnitHealth)| {
+            cache.entry(key).or_insert_with(|| attacker.attack(key.1, target, key.2)).clone()
+        };
+        let responder_after = attack(striker, responder, (first_strikes, striker_health.models, responder_health));
+        // Strike backs only depend on the number of surviving models
+        let strike_backs: HashMap<u32, Distribution<UnitHealth>> = responder_after
+            .iter()
+            .map(|(health, _)| health.models)
+            .filter(|models| *models > 0)
+            .map(|models| (models, attack(responder, striker, (!first_strikes, models, striker_health))))
+            .collect();
+        let health = responder_after.and_then(|responder_after| match strike_backs.get(&responder_after.models) {
+            Some(striker_after) => striker_after.map(|striker_after| (*striker_after, *responder_after)),
+            None => Distribution::certain((striker_health, *responder_after)),
+        });
+
+        match first_strikes {
+            true => health,
+            false => health.map(|(striker, responder)| (*responder, *striker)),
+        }
+    }
+
+    /// Health of both units after a single simulated round of combat.
+    pub fn sample_round(&self, health: (UnitHealth, UnitHealth), rng: &mut dyn RngCore) -> (UnitHealth, UnitHealth) {
+        let (striker, responder, striker_health, responder_health) = match self.first_strikes_first() {
+            true => (&self.first, &self.second, health.0, health.1),
+            false => (&self.second, &self.first, health.1, health.0),
+        };
+        if striker_health.is_destroyed() || responder_health.is_destroyed() {
+            return health;
+        }
+        let responder_after = striker.sample_attack(striker_health.models, responder, responder_health, rng);
+        let striker_after = match responder_after.is_destroyed() {
+            true => striker_health,
+            false => responder.sample_attack(responder_after.models, striker, striker_health, rng),
+        };
+        match self.first_strikes_first() {
+            true => (striker_after, responder_after),
+            false => (responder_after, striker_after),
+        }
+    }
+
+    pub fn run(&self) -> DuelResult {
+        DuelResult {
+            first: self.first.unit,
+            second: self.second.unit,
+            health: self.round(self.initial_health()),
+        }
+    }
+}
+
+/// Joint distribution of the health of both units after a duel.
+#[derive(Clone, Debug)]
+pub struct DuelResult {
+    pub first: TargetUnit,
+    pub second: TargetUnit,
+    pub health: Distribution<(UnitHealth, UnitHealth)>,
+}
+
+impl DuelResult {
+    /// Joint distribution of the models slain in the first and second units.
+    pub fn losses(&self) -> Distribution<(u32, u32)> {
+        let (first, second) = (UnitHealth::from_target(&self.first).models, UnitHealth::from_target(&self.second).models);
+        self.health.map(|(first_health, second_health)| (first - first_health.models, second - second_health.models))
+    }
+
+    pub fn first_allocation(&self) -> WoundAllocation {
+        WoundAllocation {target: self.first, health: self.health.map(|(health, _)| *health)}
+    }
+
+    pub fn second_allocation(&self) -> WoundAllocation {
+        WoundAllocation {target: self.second, health: self.health.map(|(_, health)| *health)}
+    }
+}
+
+pub fn compute_duel(duel: &Duel) -> DuelResult {
+    duel.run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probabilities::combat_stats::{Characteristic, NO_SAVE};
+    use crate::probabilities::combat_tree::SequenceBuilder;
+
+    // Two single wound models with a single attack each, killing a model on each successful wound
+    fn combatant(unit: TargetUnit, to_hit: u32, to_wound: u32) -> Combatant {
+        let attack_stats = AttackStats::new(Characteristic::Value(1), to_hit, to_wound, 0, Characteristic::Value(1));
+        Combatant::new(unit, attack_stats, DefenseStats::new(NO_SAVE, None), SequenceBuilder::new().build())
+    }
+
+    // Losses of the striker and the responder, the surviving models of the responder striking back
+    fn expected_losses(striker_kill: f64, responder_kill: f64) -> Distribution<(u32, u32)> {
+        Distribution::binomial(2, striker_kill)
+            .and_then(|responder_losses| Distribution::binomial(2 - responder_losses, responder_kill).map(|losses| (*losses, *responder_losses)))
+    }
+
+    fn assert_losses(result: &DuelResult, expected: &Distribution<(u32, u32)>) {
+        let losses = result.losses();
+        assert!((losses.total_probability() - 1.0).abs() < 1e-9);
+        for (value, proba) in expected.iter() {
+            assert!((losses.probability(value) - proba).abs() < 1e-9, "{:?}: {} != {}", value, losses.probability(value), proba);
+        }
+    }
+
+    #[test]
+    fn champion_attacks_last_as_long_as_the_unit() {
+        let attack_stats = AttackStats::new(Characteristic::Value(2), 3, 4, 0, Characteristic::Value(1))
+            .with_models(5)
+            .with_champion_attacks(1);
+        let unit = TargetUnit::new(5, 1);
+        let combatant = Combatant::new(unit, attack_stats, DefenseStats::new(4, None), SequenceBuilder::new().build());
+        let attacks = |models: u32| {
+            combatant.config(models, &combatant, UnitHealth::from_target(&unit)).attack_stats.attacks_distribution().values_and_probas()
+        };
+        assert_eq!(attacks(1), vec![(3, 1.0)]);
+        assert_eq!(attacks(0), vec![(0, 1.0)]);
+    }
+
+    #[test]
+    fn strike_first_and_strike_last_decide_the_order() {
+        let unit = TargetUnit::new(2, 1);
+        let duel = |first: Combatant, second: Combatant| Duel::new(first, second).first_strikes_first();
+        assert!(duel(combatant(unit, 4, 4), combatant(unit, 4, 4)));
+        assert!(!duel(combatant(unit, 4, 4), combatant(unit, 4, 4).with_strike_first(true)));
+        assert!(!duel(combatant(unit, 4, 4).with_strike_last(true), combatant(unit, 4, 4)));
+        assert!(duel(combatant(unit, 4, 4).with_strike_first(true), combatant(unit, 4, 4).with_strike_first(true)));
+        assert!(duel(combatant(unit, 4, 4).with_strike_last(true), combatant(unit, 4, 4).with_strike_last(true)));
+        assert!(duel(combatant(unit, 4, 4).with_strike_first(true).with_strike_last(true), combatant(unit, 4, 4)));
+    }
+
+    #[test]
+    fn survivors_strike_back() {
+        let (strong_kill, weak_kill) = (25.0 / 36.0, 0.25);
+        let unit = TargetUnit::new(2, 1);
+        let result = Duel::new(combatant(unit, 2, 2), combatant(unit, 4, 4)).run();
+        assert_losses(&result, &expected_losses(strong_kill, weak_kill));
+
+        // The strong unit wiping the weak one, there is no strike back
+        assert!((result.losses().probability(&(0, 2)) - strong_kill * strong_kill).abs() < 1e-9);
+        let first_losses = result.losses().map(|(first, _)| *first);
+        assert!((first_losses.mean() - 2.0 * weak_kill * (1.0 - strong_kill)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn the_order_of_the_strikes_changes_the_losses() {
+        let (strong_kill, weak_kill) = (25.0 / 36.0, 0.25);
+        let unit = TargetUnit::new(2, 1);
+        let swapped = |losses: &Distribution<(u32, u32)>| losses.map(|(striker, responder)| (*responder, *striker));
+        let weak_first = expected_losses(weak_kill, strong_kill);
+
+        let strike_first = Duel::new(combatant(unit, 2, 2), combatant(unit, 4, 4).with_strike_first(true)).run();
+        assert_losses(&strike_first, &swapped(&weak_first));
+        let strike_last = Duel::new(combatant(unit, 2, 2).with_strike_last(true), combatant(unit, 4, 4)).run();
+        assert_losses(&strike_last, &swapped(&weak_first));
+    }
+
+    #[test]
+    fn losses_count_from_the_models_alive() {
+        // The second unit has already lost one of its three models
+        let second = TargetUnit::new(3, 1).with_damage_taken(1);
+        let result = Duel::new(combatant(TargetUnit::new(2, 1), 2, 2), combatant(second, 4, 4)).run();
+        assert_losses(&result, &expected_losses(25.0 / 36.0, 0.25));
+    }
+}
@@ -0,0 +1,38 @@
+use std::fmt;
+
+use crate::probabilities::dice::DiceRollParseError;
+
+/// Errors raised when building combat inputs from user provided values.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatsError {
+    /// A dice expression could not be parsed.
+    DiceParse(DiceRollParseError),
+    /// A statistic has a value outside of its domain.
+    InvalidStats(String),
+    /// An object used in a rule sequence is not a rule.
+    UnknownRule(String),
+    /// The rules of a sequence are missing a step or are in the wrong order.
+    InvalidSequence(String),
+    /// A profile file could not be read or written.
+    InvalidProfile(String),
+}
+
+impl fmt::Display for StatsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatsError::DiceParse(error) => write!(f, "invalid dice expression: {}", error),
+            StatsError::InvalidStats(message) => write!(f, "{}", message),
+            StatsError::UnknownRule(name) => write!(f, "'{}' is not a rule", name),
+            StatsError::InvalidSequence(message) => write!(f, "{}", message),
+            StatsError::InvalidProfile(message) => write!(f, "invalid profile: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for StatsError {}
+
+impl From<DiceRollParseError> for StatsError {
+    fn from(error: DiceRollParseError) -> StatsError {
+        StatsError::DiceParse(error)
+    }
+}
@@ -1,5 +1,17 @@
+pub mod attacking_unit;
+pub mod battle;
 pub mod combat_stats;
 pub mod combat_tree;
+pub mod conditions;
 pub mod dice;
+pub mod dice_expression;
+pub mod distribution;
+pub mod duel;
+pub mod errors;
+pub mod monte_carlo;
 pub mod partitions;
+pub mod profiles;
 pub mod rules;
+pub mod statistics;
+pub mod turn;
+pub mod wound_allocation;
@@ -0,0 +1,173 @@
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use statrs::distribution::{ContinuousCDF, Normal};
+
+use crate::probabilities::combat_tree::{CombatConfig, CombatStatus, Rule};
+use crate::probabilities::distribution::Distribution;
+use crate::probabilities::errors::StatsError;
+
+/// Monte Carlo counterpart of `CombatTree`, running a rule sequence on sampled dice.
+///
+/// Simulations are reproducible: the same seed always gives the same samples.
+pub struct MonteCarloSimulation {
+    config: CombatConfig,
+    rng: ChaCha8Rng,
+}
+
+impl MonteCarloSimulation {
+    pub fn new(config: CombatConfig, seed: u64) -> MonteCarloSimulation {
+        MonteCarloSimulation {config, rng: ChaCha8Rng::seed_from_u64(seed)}
+    }
+
+    /// Final status of a single simulated combat.
+    pub fn sample(&mut self, sequence: &Vec<Box<dyn Rule>>) -> CombatStatus {
+        sample_status(&self.config, sequence, &mut self.rng)
+    }
+
+    pub fn run(&mut self, sequence: &Vec<Box<dyn Rule>>, samples: u32) -> SimulationResult {
+        let damages = (0..samples).map(|_| self.sample(sequence).total_damages()).collect();
+        SimulationResult::new(damages)
+    }
+}
+
+/// Final status of a single combat simulated with the given random generator.
+pub fn sample_status(config: &CombatConfig, sequence: &Vec<Box<dyn Rule>>, rng: &mut dyn RngCore) -> CombatStatus {
+    sequence.iter().fold(CombatStatus::new(), |status, rule| rule.sample(&status, config, rng))
+}
+
+/// Empirical damage distribution of a simulation.
+#[derive(Clone, Debug)]
+pub struct SimulationResult {
+    pub samples: u32,
+    pub distribution: Distribution<u32>,
+    pub mean: f64,
+    /// Sample standard deviation of the damages.
+    pub std_dev: f64,
+}
+
+impl SimulationResult {
+    pub fn new(damages: Vec<u32>) -> SimulationResult {
+        let samples = damages.len() as u32;
+        let frequency = 1.0 / samples as f64;
+        let distribution = Distribution::new(damages.iter().map(|damage| (*damage, frequency)).collect());
+        let mean = distribution.mean();
+        let std_dev = match samples {
+            0 | 1 => 0.0,
+            _ => (distribution.variance() * samples as f64 / (samples - 1) as f64).sqrt(),
+        };
+        SimulationResult {samples, distribution, mean, std_dev}
+    }
+
+    /// Standard error of the estimated mean.
+    pub fn std_error(&self) -> f64 {
+        self.std_dev / (self.samples as f64).sqrt()
+    }
+
+    /// Normal approximation interval of the mean damages at the given confidence level.
+    pub fn mean_confidence_interval(&self, confidence: f64) -> Result<(f64, f64), StatsError> {
+        let margin = z_score(confidence)? * self.std_error();
+        Ok((self.mean - margin, self.mean + margin))
+    }
+
+    /// Wilson score interval of `P(X = value)`.
+    pub fn probability_confidence_interval(&self, value: u32, confidence: f64) -> Result<(f64, f64), StatsError> {
+        wilson_interval(self.distribution.probability(&value), self.samples, confidence)
+    }
+
+    /// Wilson score interval of `P(X >= k)`.
+    pub fn probability_at_least_confidence_interval(&self, k: u32, confidence: f64) -> Result<(f64, f64), StatsError> {
+        wilson_interval(self.distribution.probability_at_least(k), self.samples, confidence)
+    }
+}
+
+/// Confidence levels are in `[0, 1)`.
+fn z_score(confidence: f64) -> Result<f64, StatsError> {
+    if !(0.0..1.0).contains(&confidence) {
+        return Err(StatsError::InvalidStats(format!("Confidence level {} is not in [0, 1)", confidence)));
+    }
+    Ok(Normal::new(0.0, 1.0).unwrap().inverse_cdf((1.0 + confidence) / 2.0))
+}
+
+fn wilson_interval(proportion: f64, samples: u32, confidence: f64) -> Result<(f64, f64), StatsError> {
+    let z = z_score(confidence)?;
+    if samples == 0 {
+        return Ok((0.0, 1.0));
+    }
+    let n = samples as f64;
+    let denominator = 1.0 + z * z / n;
+    let center = (proportion + z * z / (2.0 * n)) / denominator;
+    let margin = z * (proportion * (1.0 - proportion) / n + z * z / (4.0 * n * n)).sqrt() / denominator;
+    Ok(((center - margin).max(0.0), (center + margin).min(1.0)))
+}
+
+pub fn simulate_damages(
+    config: CombatConfig,
+    sequence: &Vec<Box<dyn Rule>>,
+    samples: u32,
+    seed: u64,
+) -> SimulationResult {
+    MonteCarloSimulation::new(config, seed).run(sequence, samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probabilities::combat_stats::{
+        AttackStats, Characteristic, CriticalBonus, CriticalThreshold, CriticalTrigger, DefenseStats, Reroll, Rerolls
+    };
+    use crate::probabilities::combat_tree::{CriticalHitEffect, SequenceBuilder, compute_damages};
+    use crate::probabilities::dice::DiceRoll;
+
+    fn simulated_config() -> CombatConfig {
+        let attack_stats = AttackStats::new(Characteristic::Value(2), 3, 4, 1, Characteristic::Value(1));
+        CombatConfig::new(attack_stats, DefenseStats::new(4, None))
+    }
+
+    #[test]
+    fn invalid_confidence_levels_are_rejected() {
+        let result = simulate_damages(simulated_config(), &SequenceBuilder::new().build(), 100, 0);
+        assert!(result.mean_confidence_interval(0.95).is_ok());
+        for confidence in [1.5, 1.0, -0.1, f64::NAN] {
+            assert!(matches!(result.mean_confidence_interval(confidence), Err(StatsError::InvalidStats(_))));
+            assert!(matches!(result.probability_confidence_interval(0, confidence), Err(StatsError::InvalidStats(_))));
+            assert!(matches!(result.probability_at_least_confidence_interval(1, confidence), Err(StatsError::InvalidStats(_))));
+        }
+    }
+
+    #[test]
+    fn exact_means_fall_in_simulated_intervals() {
+        let base = simulated_config();
+        let d3 = Characteristic::DiceRoll(DiceRoll::from_str("D3".to_string()).unwrap());
+        let configs = vec![
+            (
+                base.with_rerolls(Rerolls::new(Reroll::Failures, Reroll::SingleDie, Reroll::Ones)),
+                SequenceBuilder::new(),
+            ),
+            (
+                CombatConfig::new(
+                    base.attack_stats.with_attacks(Characteristic::Value(6)).with_damages(d3),
+                    DefenseStats::new(5, Some(5)),
+                ).with_rerolls(Rerolls::new(Reroll::Ones, Reroll::NonCritical, Reroll::None)),
+                SequenceBuilder::new().with_critical_hit(CriticalHitEffect::MortalWound),
+            ),
+            (
+                CombatConfig::new(
+                    base.attack_stats
+                        .with_models(3)
+                        .with_critical_wound(Some(CriticalThreshold::new(5)))
+                        .with_critical_bonus(Some(CriticalBonus::new(CriticalTrigger::Wound, 2, 1))),
+                    DefenseStats::new(3, Some(6)),
+                ).with_rerolls(Rerolls::new(Reroll::SingleDie, Reroll::Failures, Reroll::SingleDie)),
+                SequenceBuilder::new().with_critical_hit(CriticalHitEffect::AutoWound),
+            ),
+        ];
+        for (seed, (config, builder)) in configs.into_iter().enumerate() {
+            let sequence = builder.build();
+            let exact = compute_damages(config.clone(), &sequence).mean();
+            let (low, high) = simulate_damages(config, &sequence, 20_000, seed as u64)
+                .mean_confidence_interval(0.999)
+                .unwrap();
+            assert!(low <= exact && exact <= high, "{} not in [{}, {}]", exact, low, high);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Unit profiles stored in JSON or TOML files, see the README for the file format.
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::probabilities::attacking_unit::{AttackingUnit, WeaponProfile};
+use crate::probabilities::combat_stats::{AttackStats, Rerolls, RollModifier, TargetProfile};
+use crate::probabilities::combat_tree::{CombatContext, Rule, SequenceBuilder};
+use crate::probabilities::conditions::{Condition, ConditionalMode, ConditionalRule};
+use crate::probabilities::errors::StatsError;
+use crate::probabilities::rules::StandardRule;
+
+/// Rule of a sequence written in a profile file, rules defined in Python can not be stored.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "RuleSpecRepr", into = "RuleSpecRepr")]
+pub enum RuleSpec {
+    /// Rule provided by the crate, written as its name, e.g. `"hit"`.
+    Standard(StandardRule),
+    /// Save rule of an Anti-X weapon, see `ConditionalRule::anti`.
+    Anti {keyword: String, rend: i32},
+    /// Rule applied or modified depending on its mode, see `ConditionalRule`.
+    Conditional {
+        condition: Condition,
+        rule: Box<RuleSpec>,
+        mode: ConditionalMode,
+    },
+}
+
+// Standard rules are written as their name, next to the anti and conditional rules
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RuleSpecRepr {
+    AttackCharacteristic,
+    Hit,
+    CritMortalWound,
+    CritAutoWound,
+    CritDoubleHit,
+    Wound,
+    Save,
+    Damages,
+    Ward,
+    Anti {keyword: String, rend: i32},
+    Conditional {
+        condition: Condition,
+        rule: Box<RuleSpec>,
+        mode: ConditionalMode,
+    },
+}
+
+impl From<RuleSpecRepr> for RuleSpec {
+    fn from(repr: RuleSpecRepr) -> RuleSpec {
+        match repr {
+            RuleSpecRepr::AttackCharacteristic => RuleSpec::Standard(StandardRule::AttackCharacteristic),
+            RuleSpecRepr::Hit => RuleSpec::Standard(StandardRule::Hit),
+            RuleSpecRepr::CritMortalWound => RuleSpec::Standard(StandardRule::CritMortalWound),
+            RuleSpecRepr::CritAutoWound => RuleSpec::Standard(StandardRule::CritAutoWound),
+            RuleSpecRepr::CritDoubleHit => RuleSpec::Standard(StandardRule::CritDoubleHit),
+            RuleSpecRepr::Wound => RuleSpec::Standard(StandardRule::Wound),
+            RuleSpecRepr::Save => RuleSpec::Standard(StandardRule::Save),
+            RuleSpecRepr::Damages => RuleSpec::Standard(StandardRule::Damages),
+            RuleSpecRepr::Ward => RuleSpec::Standard(StandardRule::Ward),
+            RuleSpecRepr::Anti {keyword, rend} => RuleSpec::Anti {keyword, rend},
+            RuleSpecRepr::Conditional {condition, rule, mode} => RuleSpec::Conditional {condition, rule, mode},
+        }
+    }
+}
+
+impl From<RuleSpec> for RuleSpecRepr {
+    fn from(spec: RuleSpec) -> RuleSpecRepr {
+        match spec {
+            RuleSpec::Standard(rule) => match rule {
+                StandardRule::AttackCharacteristic => RuleSpecRepr::AttackCharacteristic,
+                StandardRule::Hit => RuleSpecRepr::Hit,
+                StandardRule::CritMortalWound => RuleSpecRepr::CritMortalWound,
+                StandardRule::CritAutoWound => RuleSpecRepr::CritAutoWound,
+                StandardRule::CritDoubleHit => RuleSpecRepr::CritDoubleHit,
+                StandardRule::Wound => RuleSpecRepr::Wound,
+                StandardRule::Save => RuleSpecRepr::Save,
+                StandardRule::Damages => RuleSpecRepr::Damages,
+                StandardRule::Ward => RuleSpecRepr::Ward,
+            },
+            RuleSpec::Anti {keyword, rend} => RuleSpecRepr::Anti {keyword, rend},
+            RuleSpec::Conditional {condition, rule, mode} => RuleSpecRepr::Conditional {condition, rule, mode},
+        }
+    }
+}
+
+impl RuleSpec {
+    pub fn rule(&self) -> Box<dyn Rule> {
+        match self {
+            RuleSpec::Standard(rule) => rule.rule(),
+            RuleSpec::Anti {keyword, rend} => Box::new(ConditionalRule::anti(keyword, *rend)),
+            RuleSpec::Conditional {condition, rule, mode} => {
+                Box::new(ConditionalRule::new(condition.clone(), rule.rule(), mode.clone()))
+            },
+        }
+    }
+}
+
+/// Standard sequence, damage and wards included.
+fn standard_sequence() -> Vec<RuleSpec> {
+    SequenceBuilder::new().standard_rules().into_iter().map(RuleSpec::Standard).collect()
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WeaponSpec {
+    pub name: String,
+    /// Also gives the models using the weapon, see `AttackStats::models`.
+    pub attack_stats: AttackStats,
+    #[serde(default)]
+    pub modifier: RollModifier,
+    #[serde(default)]
+    pub rerolls: Rerolls,
+    /// Flags of the attack, the keywords of the unit are added as attacker keywords.
+    #[serde(default)]
+    pub context: CombatContext,
+    #[serde(default = "standard_sequence")]
+    pub sequence: Vec<RuleSpec>,
+}
+
+impl WeaponSpec {
+    pub fn sequence(&self) -> Result<Vec<Box<dyn Rule>>, StatsError> {
+        SequenceBuilder::custom(self.sequence.iter().map(RuleSpec::rule).collect())
+            .map_err(|error| StatsError::InvalidSequence(format!("weapon '{}': {}", self.name, error)))
+    }
+}
+
+/// Warscroll of a unit, used both as a target and as an attacking unit.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UnitSpec {
+    pub name: String,
+    #[serde(flatten)]
+    pub profile: TargetProfile,
+    #[serde(default)]
+    pub weapons: Vec<WeaponSpec>,
+}
+
+impl UnitSpec {
+    pub fn target_profile(&self) -> TargetProfile {
+        self.profile.clone()
+    }
+
+    pub fn weapon_profile(&self, weapon: &WeaponSpec) -> Result<WeaponProfile, StatsError> {
+        let context = self.profile.keywords
+            .iter()
+            .fold(weapon.context.clone(), |context, keyword| context.with_attacker_keyword(keyword));
+        Ok(WeaponProfile::new(weapon.attack_stats.clone(), weapon.sequence()?)
+            .with_modifier(weapon.modifier)
+            .with_rerolls(weapon.rerolls)
+            .with_context(context))
+    }
+
+    pub fn attacking_unit(&self) -> Result<AttackingUnit, StatsError> {
+        let profiles = self.weapons
+            .iter()
+            .map(|weapon| self.weapon_profile(weapon))
+            .collect::<Result<Vec<WeaponProfile>, StatsError>>()?;
+        Ok(AttackingUnit::new(profiles))
+    }
+}
+
+/// Collection of warscrolls, as stored in a profile file.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct UnitLibrary {
+    #[serde(default)]
+    pub units: Vec<UnitSpec>,
+}
+
+impl UnitLibrary {
+    pub fn new(units: Vec<UnitSpec>) -> UnitLibrary {
+        UnitLibrary {units}
+    }
+
+    pub fn unit(&self, name: &str) -> Option<&UnitSpec> {
+        self.units.iter().find(|unit| unit.name == name)
+    }
+
+    pub fn unit_names(&self) -> Vec<String> {
+        self.units.iter().map(|unit| unit.name.clone()).collect()
+    }
+
+    /// Checks the rule sequences of all the weapons.
+    pub fn validate(&self) -> Result<(), StatsError> {
+        for unit in &self.units {
+            for weapon in &unit.weapons {
+                weapon.sequence()
+                    .map_err(|error| StatsError::InvalidSequence(format!("unit '{}', {}", unit.name, error)))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn from_json(text: &str) -> Result<UnitLibrary, StatsError> {
+        let library: UnitLibrary = serde_json::from_str(text)
+            .map_err(|error| StatsError::InvalidProfile(error.to_string()))?;
+        library.validate()?;
+        Ok(library)
+    }
+
+    pub fn from_toml(text: &str) -> Result<UnitLibrary, StatsError> {
+        let library: UnitLibrary = toml::from_str(text)
+            .map_err(|error| StatsError::InvalidProfile(error.to_string()))?;
+        library.validate()?;
+        Ok(library)
+    }
+
+    pub fn to_json(&self) -> Result<String, StatsError> {
+        serde_json::to_string_pretty(self).map_err(|error| StatsError::InvalidProfile(error.to_string()))
+    }
+
+    pub fn to_toml(&self) -> Result<String, StatsError> {
+        toml::to_string(self).map_err(|error| StatsError::InvalidProfile(error.to_string()))
+    }
+
+    /// Reads a `.json` or `.toml` file, the format being given by the extension.
+    pub fn load(path: &Path) -> Result<UnitLibrary, StatsError> {
+        let text = fs::read_to_string(path)
+            .map_err(|error| StatsError::InvalidProfile(format!("{}: {}", path.display(), error)))?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => UnitLibrary::from_json(&text),
+            Some("toml") => UnitLibrary::from_toml(&text),
+            _ => Err(StatsError::InvalidProfile(format!("{}: expected a .json or .toml file", path.display()))),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), StatsError> {
+        let text = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => self.to_json()?,
+            Some("toml") => self.to_toml()?,
+            _ => return Err(StatsError::InvalidProfile(format!("{}: expected a .json or .toml file", path.display()))),
+        };
+        fs::write(path, text).map_err(|error| StatsError::InvalidProfile(format!("{}: {}", path.display(), error)))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::probabilities::conditions::ConfigChange;
+
+    // The documented example is checked to stay loadable
+    const README: &str = include_str!("../../README.md");
+
+    pub(crate) fn readme_profile() -> &'static str {
+        let start = README.find("```toml\n").unwrap() + "```toml\n".len();
+        let end = start + README[start..].find("```").unwrap();
+        &README[start..end]
+    }
+
+    #[test]
+    fn profiles_are_loaded_and_saved() {
+        let library = UnitLibrary::from_toml(readme_profile()).unwrap();
+        assert_eq!(library.unit_names(), vec!["Liberators"]);
+        let unit = library.unit("Liberators").unwrap();
+        assert_eq!(unit.weapon_profile(&unit.weapons[0]).unwrap().sequence.len(), 6);
+        assert!(unit.attacking_unit().unwrap().compute_damages_against(&unit.target_profile()).mean() > 0.0);
+
+        let reloaded = UnitLibrary::from_json(&library.to_json().unwrap()).unwrap();
+        assert_eq!(reloaded.to_toml().unwrap(), library.to_toml().unwrap());
+    }
+
+    #[test]
+    fn conditional_modes_are_explicit() {
+        let spec = |mode: &str| serde_json::from_str::<RuleSpec>(
+            &format!(r#"{{"conditional": {{"condition": {{"flag": "charged"}}, "rule": "ward"{}}}}}"#, mode)
+        );
+        let expected = |mode: ConditionalMode| RuleSpec::Conditional {
+            condition: Condition::Flag("charged".to_string()),
+            rule: Box::new(RuleSpec::Standard(StandardRule::Ward)),
+            mode,
+        };
+        assert_eq!(spec(r#", "mode": "apply""#).unwrap(), expected(ConditionalMode::Apply));
+        assert_eq!(spec(r#", "mode": {"modify": []}"#).unwrap(), expected(ConditionalMode::Modify(vec![])));
+        assert!(spec("").is_err());
+
+        let library = UnitLibrary::from_toml(readme_profile()).unwrap();
+        let hit_modifier = ConditionalMode::Modify(vec![ConfigChange::HitModifier(1)]);
+        assert!(matches!(&library.unit("Liberators").unwrap().weapons[0].sequence[1], RuleSpec::Conditional {mode, ..} if *mode == hit_modifier));
+    }
+
+    #[test]
+    fn invalid_profiles_are_rejected() {
+        let invalid_sequence = readme_profile().replace("\"wound\",\n", "");
+        assert!(matches!(UnitLibrary::from_toml(&invalid_sequence), Err(StatsError::InvalidSequence(_))));
+        assert!(matches!(UnitLibrary::from_toml("[[units]]\nname = 3"), Err(StatsError::InvalidProfile(_))));
+        assert!(matches!(UnitLibrary::load(Path::new("missing.toml")), Err(StatsError::InvalidProfile(_))));
+        assert!(matches!(UnitLibrary::new(Vec::new()).save(Path::new("library.yaml")), Err(StatsError::InvalidProfile(_))));
+    }
+}
@@ -1,81 +1,315 @@
-use crate::probabilities::combat_stats::Characteristic;
-use crate::probabilities::combat_tree::{CombatNode, CombatStatus, CombatConfig, Rule};
+use crate::probabilities::combat_stats::{CriticalTrigger, Reroll, Ward};
+use crate::probabilities::combat_tree::{CombatStatus, CombatConfig, Rule, RuleStage};
+use crate::probabilities::distribution::Distribution;
 use crate::probabilities::partitions::generate_partitions_probabilities;
-use crate::probabilities::dice::DiceRoll;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+
+/// Rules provided by the crate, identifying a rule without relying on its type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StandardRule {
+    AttackCharacteristic,
+    Hit,
+    CritMortalWound,
+    CritAutoWound,
+    CritDoubleHit,
+    Wound,
+    Save,
+    Damages,
+    Ward,
+}
+
+impl StandardRule {
+    /// Every standard rule, their Python classes being registered from this list.
+    pub const ALL: [StandardRule; 9] = [
+        StandardRule::AttackCharacteristic,
+        StandardRule::Hit,
+        StandardRule::CritMortalWound,
+        StandardRule::CritAutoWound,
+        StandardRule::CritDoubleHit,
+        StandardRule::Wound,
+        StandardRule::Save,
+        StandardRule::Damages,
+        StandardRule::Ward,
+    ];
+
+    pub fn rule(&self) -> Box<dyn Rule> {
+        match self {
+            StandardRule::AttackCharacteristic => Box::new(AttackCharacteristicRule),
+            StandardRule::Hit => Box::new(HitRule),
+            StandardRule::CritMortalWound => Box::new(CritMortalWoundRule),
+            StandardRule::CritAutoWound => Box::new(CritAutoWoundRule),
+            StandardRule::CritDoubleHit => Box::new(CritDoubleHitRule),
+            StandardRule::Wound => Box::new(WoundRule),
+            StandardRule::Save => Box::new(SaveRule),
+            StandardRule::Damages => Box::new(DamagesRule),
+            StandardRule::Ward => Box::new(WardRule),
+        }
+    }
+}
 
+/// Determines the number of attacks, rolled for each attacking model.
 #[derive(Clone, Debug)]
 pub struct AttackCharacteristicRule;
-/// Dertmines the number of attacks
-impl Rule for AttackCharacteristicRule {
-    fn apply(
-        &self,
-        node: &CombatNode
-    )-> Vec<CombatNode> {
-        let attack_num_stat = node.config.attack_stats.attacks;
-
-        let values_and_probas = match attack_num_stat {
-            Characteristic::Value(value) => vec![(value, 1.0)],
-            Characteristic::DiceRoll(roll) => roll.values_and_probas()
-        };
 
-        values_and_probas.iter().map(
-            |(value, proba)| CombatNode::new(node.status.with_attacks(*value), node.config, node.probability * proba)
-        ).collect()
+impl Rule for AttackCharacteristicRule {
+    fn apply(&self, status: &CombatStatus, config: &CombatConfig) -> Distribution<CombatStatus> {
+        config.attack_stats.attacks_distribution().map(|attacks| status.with_attacks(*attacks))
     }
+    fn stage(&self) -> Option<RuleStage> {Some(RuleStage::Attacks)}
+    fn standard_rule(&self) -> Option<StandardRule> {Some(StandardRule::AttackCharacteristic)}
 }
 
-pub trait TestRollRule : Rule {
+/// Rule rolling one dice per element of the status, each roll falling into one of
+/// `outcome_count` outcomes, the last one being the failure.
+///
+/// Every test roll is a `Rule`.
+pub trait TestRollRule {
     fn roll_count(&self, status: &CombatStatus) -> u32;
-    fn partition_prior(&self, config: &CombatConfig) -> Vec<f64>;
-    fn build_node(&self, node: &CombatNode, counts: &Vec<u32>, probability: f64) -> CombatNode;
-
-    fn apply(
-        &self,
-        node: &CombatNode
-    )-> Vec<CombatNode>{
-        let probas = self.partition_prior(&node.config);
-        let nrolls = self.roll_count(&node.status);
-        let partitions = generate_partitions_probabilities(nrolls, &probas);
-        let mut nodes = vec![];
-        for (counts, proba) in partitions {
-            let new_node = self.build_node(node, &counts, proba);
-            nodes.push(new_node);
+    /// Outcome of a single unmodified roll
+    fn roll_outcome(&self, roll: u32, config: &CombatConfig) -> usize;
+    fn outcome_count(&self) -> usize {2}
+    fn reroll(&self, _config: &CombatConfig) -> Reroll {Reroll::None}
+    fn is_critical(&self, roll: u32, _config: &CombatConfig) -> bool {roll == 6}
+    fn stage(&self) -> Option<RuleStage> {None}
+    fn standard_rule(&self) -> Option<StandardRule> {None}
+    /// Status reached when the rolls fall into the outcomes as given by `counts`
+    fn build_status(&self, status: &CombatStatus, counts: &[u32], config: &CombatConfig) -> CombatStatus;
+
+    /// Whether a single dice is rerolled, single die rerolls being handled on the whole roll
+    fn is_rerolled(&self, roll: u32, outcome: usize, config: &CombatConfig, reroll: Reroll) -> bool {
+        match reroll {
+            Reroll::Ones => roll == 1,
+            Reroll::Failures => outcome == self.outcome_count() - 1,
+            Reroll::NonCritical => !self.is_critical(roll, config),
+            Reroll::None | Reroll::SingleDie => false,
         }
-        nodes
     }
-}
 
-pub trait BaseHitRule : TestRollRule {
-    fn roll_count(&self, status: &CombatStatus) -> u32 {status.attacks}
+    /// Probability of each outcome for a single dice, rerolled according to `reroll`
+    fn roll_prior(&self, config: &CombatConfig, reroll: Reroll) -> Vec<f64> {
+        let mut single_roll = vec![0.0; self.outcome_count()];
+        for roll in 1..=6 {
+            single_roll[self.roll_outcome(roll, config)] += 1.0 / 6.0;
+        }
+
+        let mut probas = vec![0.0; self.outcome_count()];
+        for roll in 1..=6 {
+            let outcome = self.roll_outcome(roll, config);
+            if self.is_rerolled(roll, outcome, config, reroll) {
+                for (index, proba) in single_roll.iter().enumerate() {
+                    probas[index] += proba / 6.0;
+                }
+            } else {
+                probas[outcome] += 1.0 / 6.0;
+            }
+        }
+        probas
+    }
+
     fn partition_prior(&self, config: &CombatConfig) -> Vec<f64> {
-        // Compute the probability of success
-        
-        let critical_proba = 1.0 / 6.0;
-        let success_proba = (1..=6).map(
-            |roll| {
-                match roll {
-                    6 => 0.0, // 6s are critical and will be counted separately
-                    1 => 0.0,
-                    _ => (config.modifier.apply_to_hit_modifier(roll) >= config.attack_stats.to_hit) as u32 as f64 / 6.0
+        self.roll_prior(config, self.reroll(config))
+    }
+
+    /// Outcome counts of the rolls and their probabilities, before any single die reroll
+    fn partitions(&self, status: &CombatStatus, config: &CombatConfig) -> Vec<(Vec<u32>, f64)> {
+        generate_partitions_probabilities(self.roll_count(status), &self.partition_prior(config))
+            .into_iter()
+            .filter(|(_, proba)| *proba > 0.0)
+            .collect()
+    }
+
+    /// Outcome counts once one of the failed dice, if any, is rolled again
+    fn reroll_single_die(&self, counts: Vec<u32>, proba: f64, config: &CombatConfig) -> Vec<(Vec<u32>, f64)> {
+        let failure = self.outcome_count() - 1;
+        if counts[failure] == 0 {
+            return vec![(counts, proba)];
+        }
+        self.roll_prior(config, Reroll::None).iter().enumerate().map(
+            |(outcome, outcome_proba)| {
+                let mut rerolled_counts = counts.clone();
+                rerolled_counts[failure] -= 1;
+                rerolled_counts[outcome] += 1;
+                (rerolled_counts, proba * outcome_proba)
+            }
+        ).collect()
+    }
+
+    /// Distribution of the states reached by enumerating the partitions of the rolls
+    fn roll(&self, status: &CombatStatus, config: &CombatConfig) -> Distribution<CombatStatus> {
+        let mut partitions = self.partitions(status, config);
+        if self.reroll(config) == Reroll::SingleDie {
+            partitions = partitions.into_iter()
+                .flat_map(|(counts, proba)| self.reroll_single_die(counts, proba, config))
+                .collect();
+        }
+
+        Distribution::new(
+            partitions.iter().map(|(counts, proba)| (self.build_status(status, counts, config), *proba)).collect()
+        )
+    }
+
+    /// Rolls the dice one by one, a single die reroll being left to the caller
+    fn sample_counts(&self, status: &CombatStatus, config: &CombatConfig, rng: &mut dyn RngCore) -> Vec<u32> {
+        let reroll = self.reroll(config);
+        let mut counts = vec![0; self.outcome_count()];
+        for _ in 0..self.roll_count(status) {
+            let mut roll = rng.gen_range(1..=6);
+            let mut outcome = self.roll_outcome(roll, config);
+            if self.is_rerolled(roll, outcome, config, reroll) {
+                roll = rng.gen_range(1..=6);
+                outcome = self.roll_outcome(roll, config);
+            }
+            counts[outcome] += 1;
+        }
+        counts
+    }
+
+    /// Rolls one of the failed dice again, returns whether there was one
+    fn sample_single_die(&self, counts: &mut [u32], config: &CombatConfig, rng: &mut dyn RngCore) -> bool {
+        let failure = self.outcome_count() - 1;
+        if counts[failure] == 0 {
+            return false;
+        }
+        counts[failure] -= 1;
+        counts[self.roll_outcome(rng.gen_range(1..=6), config)] += 1;
+        true
+    }
+
+    /// Rolls the dice one by one instead of enumerating the partitions
+    fn sample_roll(&self, status: &CombatStatus, config: &CombatConfig, rng: &mut dyn RngCore) -> CombatStatus {
+        let mut counts = self.sample_counts(status, config, rng);
+        if self.reroll(config) == Reroll::SingleDie {
+            self.sample_single_die(&mut counts, config, rng);
+        }
+        self.build_status(status, &counts, config)
+    }
+}
+
+/// Two rolls made as a single one, such as the wound rolls of hits and of critical hits.
+///
+/// A single die reroll applies once to the combined roll, to a failed die of `second` if any,
+/// as it holds the critical dice.
+fn chained_roll(
+    first: &dyn TestRollRule,
+    second: &dyn TestRollRule,
+    status: &CombatStatus,
+    config: &CombatConfig
+) -> Distribution<CombatStatus> {
+    let single_die = first.reroll(config) == Reroll::SingleDie;
+    let second_failure = second.outcome_count() - 1;
+    let mut states = Vec::new();
+    for (first_counts, first_proba) in first.partitions(status, config) {
+        let child = first.build_status(status, &first_counts, config);
+        for (second_counts, second_proba) in second.partitions(&child, config) {
+            let proba = first_proba * second_proba;
+            if single_die && second_counts[second_failure] > 0 {
+                for (counts, proba) in second.reroll_single_die(second_counts, proba, config) {
+                    states.push((second.build_status(&child, &counts, config), proba));
+                }
+            }
+            else if single_die {
+                for (counts, proba) in first.reroll_single_die(first_counts.clone(), proba, config) {
+                    let rerolled_child = first.build_status(status, &counts, config);
+                    states.push((second.build_status(&rerolled_child, &second_counts, config), proba));
                 }
             }
-        ).sum();
-    
-        vec![1.0 / 6.0, success_proba, 1.0 - success_proba - critical_proba ]
+            else {
+                states.push((second.build_status(&child, &second_counts, config), proba));
+            }
+        }
     }
+    Distribution::new(states)
+}
 
-    fn result(&self, partition: &Vec<u32>) -> (u32, u32, u32);
-    fn build_node(&self, node: &CombatNode, counts: &Vec<u32>, probability: f64) -> CombatNode {
-        let (hits, wounds, mortal_wounds) = self.result(counts);
-        CombatNode::new(
-            node.status
+/// Sampled counterpart of `chained_roll`.
+fn sample_chained_roll(
+    first: &dyn TestRollRule,
+    second: &dyn TestRollRule,
+    status: &CombatStatus,
+    config: &CombatConfig,
+    rng: &mut dyn RngCore
+) -> CombatStatus {
+    let mut first_counts = first.sample_counts(status, config, rng);
+    let child = first.build_status(status, &first_counts, config);
+    let mut second_counts = second.sample_counts(&child, config, rng);
+    if first.reroll(config) == Reroll::SingleDie
+        && !second.sample_single_die(&mut second_counts, config, rng)
+        && first.sample_single_die(&mut first_counts, config, rng) {
+        let rerolled_child = first.build_status(status, &first_counts, config);
+        return second.build_status(&rerolled_child, &second_counts, config);
+    }
+    second.build_status(&child, &second_counts, config)
+}
+
+impl<T: TestRollRule + std::fmt::Debug> Rule for T {
+    fn apply(&self, status: &CombatStatus, config: &CombatConfig) -> Distribution<CombatStatus> {
+        self.roll(status, config)
+    }
+    fn sample(&self, status: &CombatStatus, config: &CombatConfig, rng: &mut dyn RngCore) -> CombatStatus {
+        self.sample_roll(status, config, rng)
+    }
+    fn stage(&self) -> Option<RuleStage> {
+        TestRollRule::stage(self)
+    }
+    fn standard_rule(&self) -> Option<StandardRule> {
+        TestRollRule::standard_rule(self)
+    }
+}
+
+/// Hit roll, the rules only differing by what critical hits score.
+///
+/// Every hit rule is a `TestRollRule`.
+pub trait BaseHitRule {
+    /// Hits, critical hits, wounds and mortal wounds scored by the partition
+    fn result(&self, partition: &[u32]) -> (u32, u32, u32, u32);
+    fn hit_rule(&self) -> StandardRule;
+}
+
+impl<T: BaseHitRule> TestRollRule for T {
+    fn roll_count(&self, status: &CombatStatus) -> u32 {status.attacks}
+
+    // Outcomes are critical hit, hit and failure
+    fn roll_outcome(&self, roll: u32, config: &CombatConfig) -> usize {
+        let modified_roll = config.modifier.apply_to_hit_modifier(roll);
+        if config.attack_stats.critical_hit.is_critical(roll, modified_roll) {
+            0 // Critical hits are counted separately
+        }
+        else if roll != 1 && modified_roll >= config.attack_stats.to_hit {
+            1
+        }
+        else {
+            2
+        }
+    }
+
+    fn is_critical(&self, roll: u32, config: &CombatConfig) -> bool {
+        config.attack_stats.critical_hit.is_critical(roll, config.modifier.apply_to_hit_modifier(roll))
+    }
+
+    fn outcome_count(&self) -> usize {3}
+
+    fn reroll(&self, config: &CombatConfig) -> Reroll {config.rerolls.hit}
+
+    fn stage(&self) -> Option<RuleStage> {Some(RuleStage::Hit)}
+
+    fn standard_rule(&self) -> Option<StandardRule> {Some(self.hit_rule())}
+
+    fn build_status(&self, status: &CombatStatus, counts: &[u32], config: &CombatConfig) -> CombatStatus {
+        let (hits, critical_hits, wounds, mortal_wounds) = self.result(counts);
+        // Wounds scored on the hit roll come from critical hits
+        let (wounds, critical_wounds) = match config.attack_stats.has_critical_bonus(CriticalTrigger::Hit) {
+            true => (0, wounds),
+            false => (wounds, 0)
+        };
+        status
             .with_attacks(0)
             .with_hits(hits)
+            .with_critical_hits(critical_hits)
             .with_wounds(wounds)
-            .with_mortal_wounds(mortal_wounds),
-            node.config,
-            node.probability * probability
-        )
+            .with_critical_wounds(critical_wounds)
+            .with_mortal_wounds(mortal_wounds)
     }
 }
 
@@ -83,261 +317,476 @@ pub trait BaseHitRule : TestRollRule {
 pub struct HitRule;
 
 impl BaseHitRule for HitRule {
-    fn result(&self, partition: &Vec<u32>) -> (u32, u32, u32) {
-        (partition[0] + partition[1], 0, 0)
+    fn result(&self, partition: &[u32]) -> (u32, u32, u32, u32) {
+        (partition[1], partition[0], 0, 0)
     }
+    fn hit_rule(&self) -> StandardRule {StandardRule::Hit}
 }
 
-impl TestRollRule for HitRule {
-    fn roll_count(&self, status: &CombatStatus) -> u32 {
-        BaseHitRule::roll_count(self, status)
-    }
-    fn partition_prior(&self, config: &CombatConfig) -> Vec<f64> {
-        BaseHitRule::partition_prior(self, config)
+/// Wound rolls of the hits, then of the critical hits.
+#[derive(Clone, Debug)]
+pub struct WoundRule;
+
+impl Rule for WoundRule {
+    fn apply(&self, status: &CombatStatus, config: &CombatConfig) -> Distribution<CombatStatus> {
+        chained_roll(&HitWoundRoll, &CriticalHitWoundRoll, status, config)
     }
-    fn build_node(&self, node: &CombatNode, counts: &Vec<u32>, probability: f64) -> CombatNode {
-        BaseHitRule::build_node(self, node, counts, probability)
+    fn sample(&self, status: &CombatStatus, config: &CombatConfig, rng: &mut dyn RngCore) -> CombatStatus {
+        sample_chained_roll(&HitWoundRoll, &CriticalHitWoundRoll, status, config, rng)
     }
+    fn stage(&self) -> Option<RuleStage> {Some(RuleStage::Wound)}
+    fn standard_rule(&self) -> Option<StandardRule> {Some(StandardRule::Wound)}
+}
+
+// Wound rolls of the hits other than critical hits
+#[derive(Clone, Debug)]
+struct HitWoundRoll;
 
+impl TestRollRule for HitWoundRoll {
+    fn roll_count(&self, status: &CombatStatus) -> u32 {status.hits}
+    // Outcomes are critical wound, wound and failure
+    fn roll_outcome(&self, roll: u32, config: &CombatConfig) -> usize {
+        let modified_roll = config.modifier.apply_to_wound_modifier(roll);
+        match config.attack_stats.critical_wound_threshold() {
+            Some(threshold) if threshold.is_critical(roll, modified_roll) => 0,
+            _ if roll != 1 && modified_roll >= config.attack_stats.to_wound => 1,
+            _ => 2
+        }
+    }
+    fn outcome_count(&self) -> usize {3}
+    fn reroll(&self, config: &CombatConfig) -> Reroll {config.rerolls.wound}
+    fn is_critical(&self, roll: u32, config: &CombatConfig) -> bool {
+        match config.attack_stats.critical_wound_threshold() {
+            Some(threshold) => threshold.is_critical(roll, config.modifier.apply_to_wound_modifier(roll)),
+            None => roll == 6
+        }
+    }
+    fn build_status(&self, status: &CombatStatus, counts: &[u32], config: &CombatConfig) -> CombatStatus {
+        let (wounds, critical_wounds) = wound_result(counts, config, false);
+        status
+            .with_hits(0)
+            .with_critical_wounds(critical_wounds + status.critical_wounds)
+            .with_wounds(wounds + status.wounds)
+    }
 }
 
-impl Rule for HitRule {
-    fn apply(&self, node: &CombatNode) -> Vec<CombatNode> {
-        TestRollRule::apply(self, node)
+// Splits the successful wound rolls into wounds and critical wounds
+fn wound_result(counts: &[u32], config: &CombatConfig, critical_hits: bool) -> (u32, u32) {
+    match config.attack_stats.has_critical_bonus(CriticalTrigger::Hit) {
+        true if critical_hits => (0, counts[0] + counts[1]),
+        true => (counts[0] + counts[1], 0),
+        false => (counts[1], counts[0])
     }
 }
 
+// Wound rolls of critical hits, which are kept apart from the other hits
 #[derive(Clone, Debug)]
-pub struct WoundRule;
+struct CriticalHitWoundRoll;
 
-impl TestRollRule for WoundRule {
-    fn roll_count(&self, status: &CombatStatus) -> u32 {status.hits}
-    fn partition_prior(&self, config: &CombatConfig) -> Vec<f64> {
-        let success_proba = (1..=6).map(
-            |roll| {
-                match roll {
-                    1 => 0.0,
-                    _ => (config.modifier.apply_to_wound_modifier(roll) >= config.attack_stats.to_wound) as u32 as f64 / 6.0
-                }
-            }
-        ).sum();
-    
-        vec![success_proba, 1.0 - success_proba]
-
-    }
-    fn build_node(&self, node: &CombatNode, counts: &Vec<u32>, probability: f64) -> CombatNode {
-        CombatNode::new(
-            node.status
-                .with_hits(0)
-                .with_wounds(counts[0] + node.status.wounds),
-            node.config,
-            probability * node.probability
-        )
+impl TestRollRule for CriticalHitWoundRoll {
+    fn roll_count(&self, status: &CombatStatus) -> u32 {status.critical_hits}
+    fn roll_outcome(&self, roll: u32, config: &CombatConfig) -> usize {
+        HitWoundRoll.roll_outcome(roll, config)
+    }
+    fn outcome_count(&self) -> usize {3}
+    fn reroll(&self, config: &CombatConfig) -> Reroll {config.rerolls.wound}
+    fn is_critical(&self, roll: u32, config: &CombatConfig) -> bool {
+        HitWoundRoll.is_critical(roll, config)
+    }
+    fn build_status(&self, status: &CombatStatus, counts: &[u32], config: &CombatConfig) -> CombatStatus {
+        let (wounds, critical_wounds) = wound_result(counts, config, true);
+        status
+            .with_critical_hits(0)
+            .with_critical_wounds(critical_wounds + status.critical_wounds)
+            .with_wounds(wounds + status.wounds)
     }
 }
 
-impl Rule for WoundRule {
-    fn apply(&self, node: &CombatNode) -> Vec<CombatNode> {
-        TestRollRule::apply(self, node)
+/// Save rolls against the wounds, then against the critical wounds.
+#[derive(Clone, Debug)]
+pub struct SaveRule;
+
+impl Rule for SaveRule {
+    fn apply(&self, status: &CombatStatus, config: &CombatConfig) -> Distribution<CombatStatus> {
+        chained_roll(&WoundSaveRoll, &CriticalSaveRoll, status, config)
+    }
+    fn sample(&self, status: &CombatStatus, config: &CombatConfig, rng: &mut dyn RngCore) -> CombatStatus {
+        sample_chained_roll(&WoundSaveRoll, &CriticalSaveRoll, status, config, rng)
     }
+    fn stage(&self) -> Option<RuleStage> {Some(RuleStage::Save)}
+    fn standard_rule(&self) -> Option<StandardRule> {Some(StandardRule::Save)}
 }
 
+// Save rolls against the wounds other than critical wounds
 #[derive(Clone, Debug)]
-pub struct SaveRule;
+struct WoundSaveRoll;
 
-impl TestRollRule for SaveRule {
+impl TestRollRule for WoundSaveRoll {
     fn roll_count(&self, status: &CombatStatus) -> u32 {status.wounds}
-    fn partition_prior(&self, config: &CombatConfig) -> Vec<f64> {
-        let success_proba = (1..=6).map(
-            |roll| {
-                match roll {
-                    1 => 0.0,
-                    _ => (config.modifier.apply_to_wound_modifier(roll) >= config.defense_stats.to_save + config.attack_stats.rend) as u32 as f64 / 6.0
-                }
-            }
-        ).sum();
-    
-        vec![success_proba, 1.0 - success_proba]
-
-    }
-    fn build_node(&self, node: &CombatNode, counts: &Vec<u32>, probability: f64) -> CombatNode {
-        CombatNode::new(
-            node.status
-                .with_hits(0)
-                .with_wounds(node.status.wounds - counts[0]),
-            node.config,
-            probability * node.probability
-        )
+    fn roll_outcome(&self, roll: u32, config: &CombatConfig) -> usize {
+        match config.defense_stats.is_saved(roll, &config.modifier, config.attack_stats.rend) {
+            true => 0,
+            false => 1
+        }
+    }
+    fn reroll(&self, config: &CombatConfig) -> Reroll {config.rerolls.save}
+    fn build_status(&self, status: &CombatStatus, counts: &[u32], _config: &CombatConfig) -> CombatStatus {
+        status
+            .with_hits(0)
+            .with_wounds(status.wounds - counts[0])
     }
 }
 
-impl Rule for SaveRule {
-    fn apply(&self, node: &CombatNode) -> Vec<CombatNode> {
-        TestRollRule::apply(self, node)
+// Save rolls against critical wounds, which are kept apart from the other wounds
+// as they may suffer a bonus rend
+#[derive(Clone, Debug)]
+struct CriticalSaveRoll;
+
+impl TestRollRule for CriticalSaveRoll {
+    fn roll_count(&self, status: &CombatStatus) -> u32 {status.critical_wounds}
+    fn roll_outcome(&self, roll: u32, config: &CombatConfig) -> usize {
+        match config.defense_stats.is_saved(roll, &config.modifier, config.attack_stats.critical_rend()) {
+            true => 0,
+            false => 1
+        }
+    }
+    fn reroll(&self, config: &CombatConfig) -> Reroll {config.rerolls.save}
+    fn build_status(&self, status: &CombatStatus, counts: &[u32], _config: &CombatConfig) -> CombatStatus {
+        status.with_critical_wounds(status.critical_wounds - counts[0])
     }
 }
 
+/// Rolls the damage of each wound, applying damage reduction.
+///
+/// The wards negating whole wounds are rolled here, as the damage is then summed, but the damage
+/// they negate is kept as `warded_damages` until `WardRule` discards it, so that all the wards
+/// depend on `WardRule`.
 #[derive(Clone, Debug)]
 pub struct DamagesRule;
 
 impl DamagesRule {
-    fn _random_damages(roll: DiceRoll, num_wounds: u32) -> Vec<(u32, f64)> {
-        let rolls_probas = roll.values_and_probas();
-        let priors = rolls_probas.iter().map(|(_, proba)| *proba).collect();
-        let roll_values: Vec<u32> = rolls_probas.iter().map(|(value, _)| *value).collect();
-        let partitions = generate_partitions_probabilities(num_wounds, &priors);
-        partitions.iter().map(
-            |(counts, proba)| {
-                (roll_values.iter().zip(counts).map(|(value, count)| (value * count)).sum(), *proba)
-            }
-        ).collect()
+    // Damage of the wounds of a stream, and damage of those negated by per-wound wards
+    fn stream_damages(config: &CombatConfig, damages: Distribution<u32>, wounds: u32, mortal: bool) -> Distribution<(u32, u32)> {
+        let damages = config.defense_stats.reduced_damages(damages, mortal);
+        let negated = config.defense_stats.wound_negation_probability(mortal);
+        if negated == 0.0 {
+            return damages.sum_of(wounds, 0).map(|damage| (*damage, 0));
+        }
+        Distribution::binomial(wounds, negated).and_then(
+            |negated_wounds| damages.sum_of(wounds - negated_wounds, 0).combine(
+                &damages.sum_of(*negated_wounds, 0),
+                |damage, warded_damage| (*damage, *warded_damage)
+            )
+        )
     }
 }
 
 impl Rule for DamagesRule {
-    fn apply(&self, node: &CombatNode) -> Vec<CombatNode> {
-        let num_wounds = node.status.wounds + node.status.mortal_wounds;
-        //println!("{:?}", node.config.attack_stats);
-        let damages_and_probas = match node.config.attack_stats.damages {
-            Characteristic::Value(value) => vec![(value * num_wounds, 1.0)],
-            Characteristic::DiceRoll(roll) => DamagesRule::_random_damages(roll, num_wounds)
-        };
-        //println!("{:?}", damages_and_probas);
-        damages_and_probas.iter().map(
-            |(damages, proba)| {
-                CombatNode::new(
-                    node.status
-                        .with_mortal_wounds(0)
-                        .with_wounds(0)
-                        .with_damages(*damages),
-                    node.config,
-                    proba * node.probability
-                )
-            }
-        ).collect()
+    fn apply(&self, status: &CombatStatus, config: &CombatConfig) -> Distribution<CombatStatus> {
+        let attack_stats = &config.attack_stats;
+        let damages_distribution = DamagesRule::stream_damages(config, attack_stats.damages.distribution(), status.wounds, false)
+            .combine(
+                &DamagesRule::stream_damages(config, attack_stats.critical_damages_distribution(), status.critical_wounds, false),
+                |(damages, warded), (critical_damages, critical_warded)| (damages + critical_damages, warded + critical_warded)
+            );
+        let mortal_damages_distribution = DamagesRule::stream_damages(
+            config, attack_stats.mortal_damages_distribution(), status.mortal_wounds, true
+        );
+        damages_distribution.combine(
+            &mortal_damages_distribution,
+            |(damages, warded), (mortal_damages, mortal_warded)| status
+                .with_mortal_wounds(0)
+                .with_critical_wounds(0)
+                .with_wounds(0)
+                .with_damages(*damages)
+                .with_mortal_damages(*mortal_damages)
+                .with_warded_damages(warded + mortal_warded)
+        )
     }
+    fn stage(&self) -> Option<RuleStage> {Some(RuleStage::Damages)}
+    fn standard_rule(&self) -> Option<StandardRule> {Some(StandardRule::Damages)}
 }
 
 
+/// Rolls the wards negating damage point by damage point, and discards the damage negated by
+/// the wards negating whole wounds, see `DamagesRule`.
 #[derive(Clone, Debug)]
 pub struct WardRule;
 
-impl TestRollRule for WardRule {
-    fn roll_count(&self, status: &CombatStatus) -> u32 {status.damages}
-
-    fn partition_prior(&self, config: &CombatConfig) -> Vec<f64> {
-        let success_proba = (1..=6).map(
-            |roll| {
-                match roll {
-                    1 => 0.0,
-                    _ => (roll >= config.defense_stats.ward.unwrap()) as u32 as f64 / 6.0
-                }
-            }
-        ).sum();
-    
-        vec![success_proba, 1.0 - success_proba]
-
-    }
-
-    fn build_node(&self, node: &CombatNode, counts: &Vec<u32>, probability: f64) -> CombatNode {
-        CombatNode::new(
-            node.status
-                .with_damages(node.status.damages - counts[0]),
-            node.config,
-            probability * node.probability
-        )
+impl WardRule {
+    // Ward rolls to make on each damage stream
+    fn rolls(config: &CombatConfig) -> Vec<WardRoll> {
+        [false, true].into_iter().flat_map(
+            |mortal| config.defense_stats.wards_against(mortal)
+                .filter(|ward| !ward.per_wound)
+                .map(move |ward| WardRoll {on: ward.on, mortal})
+        ).collect()
     }
-
 }
 
 impl Rule for WardRule {
-    fn apply(&self, node: &CombatNode) -> Vec<CombatNode> {
-        if let Some(_) = node.config.defense_stats.ward {
-            TestRollRule::apply(self, node)
-        }
-        else {
-            vec![]
-        }
+    fn apply(&self, status: &CombatStatus, config: &CombatConfig) -> Distribution<CombatStatus> {
+        WardRule::rolls(config).iter().fold(
+            Distribution::certain(status.with_warded_damages(0)),
+            |states, roll| states.and_then(|child| roll.roll(child, config))
+        )
     }
+    fn sample(&self, status: &CombatStatus, config: &CombatConfig, rng: &mut dyn RngCore) -> CombatStatus {
+        WardRule::rolls(config).iter().fold(
+            status.with_warded_damages(0),
+            |child, roll| roll.sample_roll(&child, config, rng)
+        )
+    }
+    fn stage(&self) -> Option<RuleStage> {Some(RuleStage::Ward)}
+    fn standard_rule(&self) -> Option<StandardRule> {Some(StandardRule::Ward)}
 }
 
+// Rolls of a single ward against the damage of wounds or of mortal wounds
 #[derive(Clone, Debug)]
-pub struct CritMortalWoundRule;
-
-impl BaseHitRule for CritMortalWoundRule {
-    fn result(&self, partition: &Vec<u32>) -> (u32, u32, u32) {
-        (partition[1], 0, partition[0])
-    }
+struct WardRoll {
+    on: u32,
+    mortal: bool,
 }
 
-impl TestRollRule for CritMortalWoundRule {
+impl TestRollRule for WardRoll {
     fn roll_count(&self, status: &CombatStatus) -> u32 {
-        BaseHitRule::roll_count(self, status)
+        if self.mortal {status.mortal_damages} else {status.damages}
     }
-    fn partition_prior(&self, config: &CombatConfig) -> Vec<f64> {
-        BaseHitRule::partition_prior(self, config)
+    fn roll_outcome(&self, roll: u32, _config: &CombatConfig) -> usize {
+        if Ward::new(self.on).is_success(roll) {0} else {1}
     }
-    fn build_node(&self, node: &CombatNode, counts: &Vec<u32>, probability: f64) -> CombatNode {
-        BaseHitRule::build_node(self, node, counts, probability)
+    fn build_status(&self, status: &CombatStatus, counts: &[u32], _config: &CombatConfig) -> CombatStatus {
+        match self.mortal {
+            true => status.with_mortal_damages(status.mortal_damages - counts[0]),
+            false => status.with_damages(status.damages - counts[0])
+        }
     }
 }
 
-impl Rule for CritMortalWoundRule {
-    fn apply(&self, node: &CombatNode) -> Vec<CombatNode> {
-        TestRollRule::apply(self, node)
+#[derive(Clone, Debug)]
+pub struct CritMortalWoundRule;
+
+impl BaseHitRule for CritMortalWoundRule {
+    fn result(&self, partition: &[u32]) -> (u32, u32, u32, u32) {
+        (partition[1], 0, 0, partition[0])
     }
+    fn hit_rule(&self) -> StandardRule {StandardRule::CritMortalWound}
 }
 
 #[derive(Clone, Debug)]
 pub struct CritAutoWoundRule;
 
 impl BaseHitRule for CritAutoWoundRule {
-    fn result(&self, partition: &Vec<u32>) -> (u32, u32, u32) {
-        (partition[1], partition[0], 0)
+    fn result(&self, partition: &[u32]) -> (u32, u32, u32, u32) {
+        (partition[1], 0, partition[0], 0)
     }
+    fn hit_rule(&self) -> StandardRule {StandardRule::CritAutoWound}
 }
 
-impl TestRollRule for CritAutoWoundRule {
-    fn roll_count(&self, status: &CombatStatus) -> u32 {
-        BaseHitRule::roll_count(self, status)
-    }
-    fn partition_prior(&self, config: &CombatConfig) -> Vec<f64> {
-        BaseHitRule::partition_prior(self, config)
-    }
-    fn build_node(&self, node: &CombatNode, counts: &Vec<u32>, probability: f64) -> CombatNode {
-        BaseHitRule::build_node(self, node, counts, probability)
+#[derive(Clone, Debug)]
+pub struct CritDoubleHitRule;
+
+impl BaseHitRule for CritDoubleHitRule {
+    fn result(&self, partition: &[u32]) -> (u32, u32, u32, u32) {
+        // Only one of the two hits of a critical is a critical hit
+        (partition[0] + partition[1], partition[0], 0, 0)
     }
+    fn hit_rule(&self) -> StandardRule {StandardRule::CritDoubleHit}
 }
 
-impl Rule for CritAutoWoundRule {
-    fn apply(&self, node: &CombatNode) -> Vec<CombatNode> {
-        TestRollRule::apply(self, node)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probabilities::combat_stats::{
+        AttackStats, Characteristic, CriticalBonus, CriticalThreshold, DamageReduction, DefenseStats, NO_SAVE, Rerolls, WardScope
+    };
+    use crate::probabilities::dice::DiceRoll;
+    use crate::probabilities::combat_tree::{CriticalHitEffect, SequenceBuilder, compute_damages};
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    fn single_wound_reroll_config() -> CombatConfig {
+        let attack_stats = AttackStats::new(Characteristic::Value(2), 2, 4, 0, Characteristic::Value(1));
+        CombatConfig::new(attack_stats, DefenseStats::new(NO_SAVE, None))
+            .with_rerolls(Rerolls::none().with_wound(Reroll::SingleDie))
+    }
+
+    fn wounds_distribution(status: &CombatStatus, config: &CombatConfig) -> Vec<f64> {
+        let mut probas = vec![0.0; 3];
+        for (child, proba) in HitRule.apply(status, config).iter() {
+            for (final_status, final_proba) in WoundRule.apply(child, config).iter() {
+                probas[(final_status.wounds + final_status.critical_wounds) as usize] += proba * final_proba;
+            }
+        }
+        probas
+    }
+
+    // Every die of two attacks hitting on 2+ and wounding on 4+, one failed wound roll being rerolled
+    fn enumerated_wounds_distribution() -> Vec<f64> {
+        let mut probas = vec![0.0; 3];
+        for hit_rolls in 0..36 {
+            let hits = [hit_rolls % 6 + 1, hit_rolls / 6 + 1].iter().filter(|&&roll| roll >= 2).count() as u32;
+            for wound_rolls in 0..6u32.pow(hits) {
+                let rolls: Vec<u32> = (0..hits).map(|index| wound_rolls / 6u32.pow(index) % 6 + 1).collect();
+                let wounds = rolls.iter().filter(|&&roll| roll >= 4).count();
+                for reroll in 1..=6 {
+                    let extra = (wounds < rolls.len() && reroll >= 4) as usize;
+                    probas[wounds + extra] += 1.0 / 36.0 / 6f64.powi(hits as i32) / 6.0;
+                }
+            }
+        }
+        probas
     }
-}
 
-#[derive(Clone, Debug)]
-pub struct CritDoubleHitRule;
+    #[test]
+    fn single_die_reroll_is_applied_once_per_rule() {
+        let config = single_wound_reroll_config();
+        let expected = enumerated_wounds_distribution();
+        let computed = wounds_distribution(&CombatStatus::new().with_attacks(2), &config);
+        for (computed, expected) in computed.iter().zip(expected.iter()) {
+            assert!((computed - expected).abs() < 1e-9, "{:?} != {:?}", computed, expected);
+        }
+        assert!((expected[2] - 0.3472).abs() < 1e-4);
+    }
+
+    #[test]
+    fn sampled_single_die_reroll_matches_enumeration() {
+        let config = single_wound_reroll_config();
+        let expected = enumerated_wounds_distribution();
+        let mut rng = ChaCha8Rng::seed_from_u64(5);
+        let samples = 100_000;
+        let mut counts = [0; 3];
+        for _ in 0..samples {
+            let child = HitRule.sample(&CombatStatus::new().with_attacks(2), &config, &mut rng);
+            let status = WoundRule.sample(&child, &config, &mut rng);
+            counts[(status.wounds + status.critical_wounds) as usize] += 1;
+        }
+        for (count, expected) in counts.iter().zip(expected.iter()) {
+            assert!((*count as f64 / samples as f64 - expected).abs() < 0.01);
+        }
+    }
 
-impl BaseHitRule for CritDoubleHitRule {
-    fn result(&self, partition: &Vec<u32>) -> (u32, u32, u32) {
-        (2 * partition[0] + partition[1], 0, 0)
+    #[test]
+    fn wards_are_only_rolled_by_the_ward_rule() {
+        let attack_stats = AttackStats::new(Characteristic::Value(4), 3, 3, 0, Characteristic::Value(2));
+        let wards = [Ward::new(5).with_per_wound(true), Ward::new(6)];
+        let warded = CombatConfig::new(attack_stats.clone(), DefenseStats::new(NO_SAVE, None).with_wards(wards.to_vec()));
+        let unwarded = CombatConfig::new(attack_stats, DefenseStats::new(NO_SAVE, None));
+        let damages = |config: &CombatConfig, ward: bool| {
+            compute_damages(config.clone(), &SequenceBuilder::new().with_ward(ward).build()).values_and_probas()
+        };
+        for ((value, proba), (expected_value, expected_proba)) in damages(&warded, false).into_iter().zip(damages(&unwarded, false)) {
+            assert_eq!(value, expected_value);
+            assert!((proba - expected_proba).abs() < 1e-12);
+        }
+        assert!(Distribution::new(damages(&warded, true)).mean() < Distribution::new(damages(&unwarded, true)).mean());
+
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let status = CombatStatus::new().with_wounds(10);
+        let sampled = DamagesRule.sample(&status, &warded, &mut rng);
+        assert_eq!(sampled.total_damages(), 20);
+        assert_eq!(WardRule.sample(&sampled, &warded, &mut rng).warded_damages, 0);
     }
-}
 
-impl TestRollRule for CritDoubleHitRule {
-    fn roll_count(&self, status: &CombatStatus) -> u32 {
-        BaseHitRule::roll_count(self, status)
+    // Total damage of the given status once damage and wards are rolled
+    fn warded_damages(defense_stats: DefenseStats, damages: Characteristic, status: CombatStatus) -> Distribution<u32> {
+        let attack_stats = AttackStats::new(Characteristic::Value(1), 4, 4, 0, damages);
+        let config = CombatConfig::new(attack_stats, defense_stats);
+        DamagesRule.apply(&status, &config).and_then(|status| WardRule.apply(status, &config)).map(CombatStatus::total_damages)
     }
-    fn partition_prior(&self, config: &CombatConfig) -> Vec<f64> {
-        BaseHitRule::partition_prior(self, config)
+
+    fn assert_probas(distribution: &Distribution<u32>, expected: &[(u32, f64)]) {
+        assert!((distribution.total_probability() - 1.0).abs() < 1e-12);
+        for (value, proba) in expected {
+            assert!((distribution.probability(value) - proba).abs() < 1e-12, "{}: {:?}", value, distribution);
+        }
     }
-    fn build_node(&self, node: &CombatNode, counts: &Vec<u32>, probability: f64) -> CombatNode {
-        BaseHitRule::build_node(self, node, counts, probability)
+
+    #[test]
+    fn stacked_wards_are_all_rolled() {
+        let wards = vec![Ward::new(5), Ward::new(6)];
+        let damages = warded_damages(DefenseStats::new(NO_SAVE, None).with_wards(wards), Characteristic::Value(1), CombatStatus::new().with_wounds(1));
+        assert_probas(&damages, &[(1, 2.0 / 3.0 * 5.0 / 6.0), (0, 1.0 - 2.0 / 3.0 * 5.0 / 6.0)]);
+
+        // Per-wound wards stack with the wards rolled against each damage point
+        let wards = vec![Ward::new(4).with_per_wound(true), Ward::new(4)];
+        let damages = warded_damages(DefenseStats::new(NO_SAVE, None).with_wards(wards), Characteristic::Value(1), CombatStatus::new().with_wounds(1));
+        assert_probas(&damages, &[(1, 0.25), (0, 0.75)]);
+    }
+
+    #[test]
+    fn mortal_wound_wards_only_apply_to_mortal_wounds() {
+        let defense_stats = DefenseStats::new(NO_SAVE, None).with_wards(vec![Ward::new(5).with_scope(WardScope::MortalWoundsOnly)]);
+        let wounds = warded_damages(defense_stats.clone(), Characteristic::Value(1), CombatStatus::new().with_wounds(1));
+        assert_probas(&wounds, &[(1, 1.0)]);
+        let mortal_wounds = warded_damages(defense_stats, Characteristic::Value(1), CombatStatus::new().with_mortal_wounds(1));
+        assert_probas(&mortal_wounds, &[(1, 2.0 / 3.0), (0, 1.0 / 3.0)]);
+    }
+
+    #[test]
+    fn per_wound_wards_negate_whole_wounds() {
+        let status = CombatStatus::new().with_wounds(1);
+        let per_damage = warded_damages(DefenseStats::new(NO_SAVE, Some(4)), Characteristic::Value(2), status);
+        assert_probas(&per_damage, &[(0, 0.25), (1, 0.5), (2, 0.25)]);
+        let per_wound = DefenseStats::new(NO_SAVE, None).with_wards(vec![Ward::new(4).with_per_wound(true)]);
+        assert_probas(&warded_damages(per_wound, Characteristic::Value(2), status), &[(0, 0.5), (2, 0.5)]);
+    }
+
+    #[test]
+    fn damage_reduction_keeps_a_minimum() {
+        let reduction = DamageReduction::new(1, 1);
+        assert_eq!([0, 1, 2, 3, 4].map(|damage| reduction.apply(damage)), [0, 1, 1, 2, 3]);
+
+        // -1 damage to a minimum of 1 against D3 damage, mortal wounds not being reduced
+        let defense_stats = DefenseStats::new(NO_SAVE, None).with_damage_reduction(Some(reduction));
+        let d3 = || Characteristic::DiceRoll(DiceRoll::from_str("D3".to_string()).unwrap());
+        let wounds = warded_damages(defense_stats.clone(), d3(), CombatStatus::new().with_wounds(1));
+        assert_probas(&wounds, &[(1, 2.0 / 3.0), (2, 1.0 / 3.0)]);
+        let mortal_wounds = warded_damages(defense_stats, d3(), CombatStatus::new().with_mortal_wounds(1));
+        assert_probas(&mortal_wounds, &[(1, 1.0 / 3.0), (2, 1.0 / 3.0), (3, 1.0 / 3.0)]);
+    }
+
+    #[test]
+    fn mortal_wounds_skip_saves_and_keep_their_own_damage() {
+        // Critical hits on 6s inflict a mortal wound of 3 damage, other wounds 1 damage
+        let attack_stats = AttackStats::new(Characteristic::Value(1), 2, 4, 0, Characteristic::Value(1))
+            .with_mortal_damages(Some(Characteristic::Value(3)));
+        let mortal_ward = Ward::new(4).with_scope(WardScope::MortalWoundsOnly);
+        let sequence = SequenceBuilder::new().with_critical_hit(CriticalHitEffect::MortalWound).build();
+        let damages = |to_save: u32| {
+            let defense_stats = DefenseStats::new(to_save, None).with_wards(vec![mortal_ward]);
+            compute_damages(CombatConfig::new(attack_stats.clone(), defense_stats), &sequence)
+        };
+
+        // Each point of the mortal wound is warded on 4+, the wound is saved on 2+ and never warded
+        let (mortal, wound) = (1.0 / 6.0, 4.0 / 6.0 * 0.5 / 6.0);
+        let expected = [(1, wound + mortal * 3.0 / 8.0), (2, mortal * 3.0 / 8.0), (3, mortal / 8.0)];
+        assert_probas(&damages(2), &expected);
+
+        // Saves only change the damage of the wounds
+        let unsaved = damages(NO_SAVE);
+        for (value, proba) in expected.into_iter().skip(1) {
+            assert!((unsaved.probability(&value) - proba).abs() < 1e-12);
+        }
+        assert!((unsaved.probability(&1) - (4.0 / 6.0 * 0.5 + mortal * 3.0 / 8.0)).abs() < 1e-12);
     }
-}
 
-impl Rule for CritDoubleHitRule {
-    fn apply(&self, node: &CombatNode) -> Vec<CombatNode> {
-        TestRollRule::apply(self, node)
+    #[test]
+    fn standard_rules_are_identified() {
+        for standard_rule in StandardRule::ALL {
+            assert_eq!(standard_rule.rule().standard_rule(), Some(standard_rule));
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn critical_wound_bonuses_default_to_unmodified_sixes() {
+        let attack_stats = AttackStats::new(Characteristic::Value(4), 3, 4, 0, Characteristic::Value(1))
+            .with_critical_bonus(Some(CriticalBonus::new(CriticalTrigger::Wound, 1, 1)));
+        let damages = |attack_stats: AttackStats| {
+            compute_damages(CombatConfig::new(attack_stats, DefenseStats::new(4, None)), &SequenceBuilder::new().build()).mean()
+        };
+        let explicit = attack_stats.with_critical_wound(Some(CriticalThreshold::unmodified_six()));
+        assert!((damages(attack_stats.clone()) - damages(explicit)).abs() < 1e-12);
+        assert!(damages(attack_stats.clone()) > damages(attack_stats.with_critical_bonus(None)));
+    }
+}
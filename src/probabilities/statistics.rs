@@ -0,0 +1,169 @@
+use crate::probabilities::distribution::Distribution;
+use crate::probabilities::errors::StatsError;
+
+// Tolerance used when comparing cumulative probabilities to a quantile level
+const CDF_TOLERANCE: f64 = 1e-12;
+
+/// Summary statistics of a damage distribution.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DistributionSummary {
+    pub mean: f64,
+    pub variance: f64,
+    pub std_dev: f64,
+    pub median: u32,
+    pub mode: u32,
+    /// `P(X <= k)` for each value `k` of the distribution.
+    pub cdf: Vec<(u32, f64)>,
+    /// `P(X >= k)` for each value `k` of the distribution.
+    pub survival: Vec<(u32, f64)>,
+}
+
+impl DistributionSummary {
+    /// Smallest value `k` such that `P(X <= k) >= q`, for `q` in `[0, 1]`.
+    pub fn quantile(&self, q: f64) -> Result<u32, StatsError> {
+        Ok(_quantile(&self.cdf, _check_level(q)?))
+    }
+
+    /// `P(X >= k)`.
+    pub fn probability_at_least(&self, k: u32) -> f64 {
+        self.survival
+            .iter()
+            .find(|(value, _)| *value >= k)
+            .map_or(0.0, |(_, proba)| *proba)
+    }
+}
+
+fn _check_level(q: f64) -> Result<f64, StatsError> {
+    if !(0.0..=1.0).contains(&q) {
+        return Err(StatsError::InvalidStats(format!("Quantile level {} is not in [0, 1]", q)));
+    }
+    Ok(q)
+}
+
+fn _quantile(cdf: &[(u32, f64)], q: f64) -> u32 {
+    cdf.iter()
+        .find(|(_, proba)| *proba >= q - CDF_TOLERANCE)
+        .or(cdf.last())
+        .map_or(0, |(value, _)| *value)
+}
+
+impl Distribution<u32> {
+    pub fn mean(&self) -> f64 {
+        self.iter().map(|(value, proba)| *value as f64 * proba).sum()
+    }
+
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+        self.iter()
+            .map(|(value, proba)| (*value as f64 - mean).powi(2) * proba)
+            .sum()
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// Most probable value, the smallest one in case of ties.
+    pub fn mode(&self) -> u32 {
+        self.iter()
+            .fold(None, |best: Option<(u32, f64)>, (value, proba)| match best {
+                Some((_, best_proba)) if best_proba >= *proba => best,
+                _ => Some((*value, *proba)),
+            })
+            .map_or(0, |(value, _)| value)
+    }
+
+    /// `P(X <= k)` for each value `k` of the distribution.
+    pub fn cdf(&self) -> Vec<(u32, f64)> {
+        let mut cumulated = 0.0;
+        self.iter()
+            .map(|(value, proba)| {
+                cumulated += proba;
+                (*value, cumulated)
+            })
+            .collect()
+    }
+
+    /// `P(X >= k)` for each value `k` of the distribution.
+    pub fn survival(&self) -> Vec<(u32, f64)> {
+        let mut cumulated = 0.0;
+        let mut survival: Vec<(u32, f64)> = self
+            .iter()
+            .rev()
+            .map(|(value, proba)| {
+                cumulated += proba;
+                (*value, cumulated)
+            })
+            .collect();
+        survival.reverse();
+        survival
+    }
+
+    /// Smallest value `k` such that `P(X <= k) >= q`, for `q` in `[0, 1]`.
+    pub fn quantile(&self, q: f64) -> Result<u32, StatsError> {
+        Ok(_quantile(&self.cdf(), _check_level(q)?))
+    }
+
+    pub fn median(&self) -> u32 {
+        _quantile(&self.cdf(), 0.5)
+    }
+
+    /// `P(X >= k)`.
+    pub fn probability_at_least(&self, k: u32) -> f64 {
+        self.iter()
+            .filter(|(value, _)| *value >= k)
+            .map(|(_, proba)| proba)
+            .sum()
+    }
+
+    pub fn summary(&self) -> DistributionSummary {
+        let variance = self.variance();
+        DistributionSummary {
+            mean: self.mean(),
+            variance,
+            std_dev: variance.sqrt(),
+            median: self.median(),
+            mode: self.mode(),
+            cdf: self.cdf(),
+            survival: self.survival(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d6() -> Distribution<u32> {
+        Distribution::uniform((1..=6).collect())
+    }
+
+    #[test]
+    fn moments_of_a_die() {
+        assert!((d6().mean() - 3.5).abs() < 1e-12);
+        assert!((d6().variance() - 35.0 / 12.0).abs() < 1e-12);
+        assert_eq!(d6().mode(), 1);
+        assert_eq!(d6().median(), 3);
+    }
+
+    #[test]
+    fn cumulative_probabilities_of_a_die() {
+        let summary = d6().summary();
+        assert!((summary.cdf[1].1 - 2.0 / 6.0).abs() < 1e-12);
+        assert!((summary.survival[1].1 - 5.0 / 6.0).abs() < 1e-12);
+        assert!((summary.probability_at_least(5) - d6().probability_at_least(5)).abs() < 1e-12);
+        assert_eq!(summary.probability_at_least(7), 0.0);
+    }
+
+    #[test]
+    fn quantiles_are_checked() {
+        let summary = d6().summary();
+        assert_eq!(summary.quantile(0.0).unwrap(), 1);
+        assert_eq!(d6().quantile(0.5).unwrap(), 3);
+        assert_eq!(summary.quantile(1.0).unwrap(), 6);
+        for q in [-0.1, 1.5, f64::NAN] {
+            assert!(matches!(d6().quantile(q), Err(StatsError::InvalidStats(_))));
+            assert!(matches!(summary.quantile(q), Err(StatsError::InvalidStats(_))));
+        }
+    }
+}
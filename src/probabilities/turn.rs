@@ -0,0 +1,84 @@
+use crate::probabilities::combat_stats::TargetUnit;
+use crate::probabilities::combat_tree::{CombatConfig, Rule};
+use crate::probabilities::wound_allocation::WoundAllocation;
+
+/// Attack sequence resolved during one phase of a turn, e.g. shooting, combat or an
+/// end-of-turn mortal wound ability.
+///
+/// As with `compute_kills`, damage and wards are rolled wound by wound during the allocation.
+#[derive(Debug)]
+pub struct Phase {
+    pub name: String,
+    pub config: CombatConfig,
+    pub sequence: Vec<Box<dyn Rule>>,
+}
+
+impl Phase {
+    pub fn new(name: &str, config: CombatConfig, sequence: Vec<Box<dyn Rule>>) -> Phase {
+        Phase {name: name.to_string(), config, sequence}
+    }
+}
+
+/// Phases resolved in order against the same target, each one allocating its damage
+/// to the models that survived the previous ones.
+#[derive(Debug, Default)]
+pub struct Turn {
+    pub phases: Vec<Phase>,
+}
+
+impl Turn {
+    pub fn new(phases: Vec<Phase>) -> Turn {
+        Turn {phases}
+    }
+
+    pub fn add_phase(&mut self, phase: Phase) {
+        self.phases.push(phase);
+    }
+
+    /// Health of the target after each phase, in the order of the phases.
+    pub fn run(&self, target: TargetUnit) -> Vec<WoundAllocation> {
+        let mut allocation = WoundAllocation::new(target);
+        self.phases.iter().map(|phase| {
+            allocation = allocation.allocate_attack(phase.config.clone(), &phase.sequence);
+            allocation.clone()
+        }).collect()
+    }
+}
+
+/// Health of the target at the end of the turn.
+pub fn compute_turn(turn: &Turn, target: TargetUnit) -> WoundAllocation {
+    turn.run(target).pop().unwrap_or_else(|| WoundAllocation::new(target))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probabilities::combat_stats::{AttackStats, Characteristic, DefenseStats, NO_SAVE};
+    use crate::probabilities::combat_tree::SequenceBuilder;
+    use crate::probabilities::wound_allocation::UnitHealth;
+
+    #[test]
+    fn phases_start_from_the_health_left_by_the_previous_ones() {
+        // A single attack wounding on 25/36 for 1 damage, against models with 2 wounds
+        let attack_stats = AttackStats::new(Characteristic::Value(1), 2, 2, 0, Characteristic::Value(1));
+        let config = CombatConfig::new(attack_stats, DefenseStats::new(NO_SAVE, None));
+        let phase = |name: &str| Phase::new(name, config.clone(), SequenceBuilder::new().build());
+        let turn = Turn::new(vec![phase("shooting"), phase("combat")]);
+        let target = TargetUnit::new(3, 2);
+        let wound = 25.0 / 36.0;
+
+        let allocations = turn.run(target);
+        assert_eq!(allocations.len(), 2);
+        assert!(allocations[0].kill_probability(1) < 1e-12);
+        // A model is only slain if both phases wound it
+        let end = &allocations[1];
+        assert!((end.kill_probability(1) - wound * wound).abs() < 1e-12);
+        let damaged = UnitHealth {models: 3, current_wounds: 1};
+        assert!((end.health.probability(&damaged) - 2.0 * wound * (1.0 - wound)).abs() < 1e-12);
+        assert_eq!(compute_turn(&turn, target).health.values_and_probas(), end.health.values_and_probas());
+
+        // Resolved on its own, the second phase slays nothing
+        let alone = Turn::new(vec![phase("combat")]).run(target);
+        assert!(alone[0].kill_probability(1) < 1e-12);
+    }
+}
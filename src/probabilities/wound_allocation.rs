@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+
+use crate::probabilities::combat_stats::{TargetProfile, TargetUnit};
+use crate::probabilities::combat_tree::{CombatConfig, CombatStatus, CombatTree, Rule, RuleStage};
+use crate::probabilities::distribution::Distribution;
+use crate::probabilities::rules::DamagesRule;
+use rand::RngCore;
+
+/// Health of a unit while damage is being allocated to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UnitHealth {
+    /// Models still alive.
+    pub models: u32,
+    /// Wounds left on the model currently receiving damage.
+    pub current_wounds: u32,
+}
+
+impl UnitHealth {
+    /// A model whose damage taken reaches its wounds is removed before the allocation.
+    pub fn from_target(target: &TargetUnit) -> UnitHealth {
+        if target.models == 0 || target.damage_taken >= target.wounds_per_model {
+            let models = target.models.saturating_sub(1);
+            UnitHealth {models, current_wounds: if models > 0 {target.wounds_per_model} else {0}}
+        } else {
+            UnitHealth {models: target.models, current_wounds: target.wounds_per_model - target.damage_taken}
+        }
+    }
+
+    pub fn is_destroyed(&self) -> bool {
+        self.models == 0
+    }
+
+    pub fn remaining_wounds(&self, wounds_per_model: u32) -> u32 {
+        match self.models {
+            0 => 0,
+            models => self.current_wounds + (models - 1) * wounds_per_model,
+        }
+    }
+
+    /// Allocates the damage of a single wound, damage exceeding the wounds of the model is lost.
+    pub fn allocate(&self, damage: u32, wounds_per_model: u32) -> UnitHealth {
+        if damage == 0 || self.is_destroyed() {
+            *self
+        } else if damage >= self.current_wounds {
+            let models = self.models - 1;
+            UnitHealth {models, current_wounds: if models > 0 {wounds_per_model} else {0}}
+        } else {
+            UnitHealth {models: self.models, current_wounds: self.current_wounds - damage}
+        }
+    }
+}
+
+/// Distribution of the health of a target unit after damage allocation.
+#[derive(Clone, Debug)]
+pub struct WoundAllocation {
+    pub target: TargetUnit,
+    pub health: Distribution<UnitHealth>,
+}
+
+impl WoundAllocation {
+    pub fn new(target: TargetUnit) -> WoundAllocation {
+        WoundAllocation {target, health: Distribution::certain(UnitHealth::from_target(&target))}
+    }
+
+    /// Allocates a random number of wounds, each inflicting an independent amount of damage.
+    pub fn allocate(&self, wounds: &Distribution<u32>, damage_per_wound: &Distribution<u32>) -> WoundAllocation {
+        let max_wounds = wounds.iter().map(|(n, _)| *n).max().unwrap_or(0);
+        let health_after = self.health_after(&self.health, max_wounds, damage_per_wound);
+
+        WoundAllocation {
+            target: self.target,
+            health: Distribution::mixture(
+                wounds.iter().map(|(n, proba)| (health_after[*n as usize].clone(), *proba)).collect()
+            ),
+        }
+    }
+
+    /// Allocates several streams of wounds, each with its own damage, given the joint distribution
+    /// of their number of wounds. Streams are allocated in order.
+    pub fn allocate_streams(&self, wounds: &Distribution<Vec<u32>>, damage_per_wound: &[Distribution<u32>]) -> WoundAllocation {
+        let mut cache = HashMap::new();
+        let components = wounds.iter().map(
+            |(counts, proba)| (self.health_after_streams(counts, damage_per_wound, &mut cache), *proba)
+        ).collect();
+        WoundAllocation {target: self.target, health: Distribution::mixture(components)}
+    }
+
+    // Health after the given number of wounds of each stream, memoized on the wound counts
+    fn health_after_streams(
+        &self,
+        counts: &[u32],
+        damage_per_wound: &[Distribution<u32>],
+        cache: &mut HashMap<Vec<u32>, Distribution<UnitHealth>>,
+    ) -> Distribution<UnitHealth> {
+        let Some(stream) = counts.iter().rposition(|count| *count > 0) else {
+            return self.health.clone();
+        };
+        if let Some(health) = cache.get(counts) {
+            return health.clone();
+        }
+        let mut previous = counts[..=stream].to_vec();
+        previous[stream] -= 1;
+        let wounds_per_model = self.target.wounds_per_model;
+        let health = self.health_after_streams(&previous, damage_per_wound, cache).and_then(
+            |health| damage_per_wound[stream].map(|damage| health.allocate(*damage, wounds_per_model))
+        );
+        cache.insert(counts.to_vec(), health.clone());
+        health
+    }
+
+    // Health after 0, 1, ..., max_wounds wounds
+    fn health_after(
+        &self,
+        initial: &Distribution<UnitHealth>,
+        max_wounds: u32,
+        damage_per_wound: &Distribution<u32>,
+    ) -> Vec<Distribution<UnitHealth>> {
+        let wounds_per_model = self.target.wounds_per_model;
+        let mut health_after = vec![initial.clone()];
+        for _ in 0..max_wounds {
+            let next = health_after.last().unwrap().and_then(
+                |health| damage_per_wound.map(|damage| health.allocate(*damage, wounds_per_model))
+            );
+            health_after.push(next);
+        }
+        health_after
+    }
+
+    /// Allocates the unsaved wounds of an attack sequence, see `compute_kills`.
+    ///
+    /// When the context of the attack gives the number of target models, it is updated with
+    /// the models still alive in each state of the target.
+    pub fn allocate_attack(&self, config: CombatConfig, sequence: &Vec<Box<dyn Rule>>) -> WoundAllocation {
+        if config.context.target_models.is_none() {
+            return self.allocate_attack_with(config, sequence);
+        }
+        let components = self.health.iter().map(|(health, proba)| {
+            let allocation = WoundAllocation {target: self.target, health: Distribution::certain(*health)};
+            let config = config.with_context(config.context.with_target_models(health.models));
+            (allocation.allocate_attack_with(config, sequence).health, *proba)
+        }).collect();
+        WoundAllocation {target: self.target, health: Distribution::mixture(components)}
+    }
+
+    fn allocate_attack_with(&self, config: CombatConfig, sequence: &Vec<Box<dyn Rule>>) -> WoundAllocation {
+        let damages = allocation_damages(&config, sequence);
+        let mut tree = CombatTree::new(config);
+        for rule in rolls_before_allocation(sequence) {
+            tree.apply_rule(rule);
+        }
+        self.allocate_streams(&tree.retrieve_wound_streams_distribution(), &damages)
+    }
+
+    /// Models slain by the allocated damage, not counting a model already slain by the damage taken.
+    pub fn models_slain(&self) -> Distribution<u32> {
+        let models = UnitHealth::from_target(&self.target).models;
+        self.health.map(|health| models - health.models)
+    }
+
+    pub fn remaining_wounds(&self) -> Distribution<u32> {
+        let wounds_per_model = self.target.wounds_per_model;
+        self.health.map(|health| health.remaining_wounds(wounds_per_model))
+    }
+
+    /// Probability to slay at least `n` models.
+    pub fn kill_probability(&self, n: u32) -> f64 {
+        self.models_slain().iter().filter(|(slain, _)| *slain >= n).map(|(_, proba)| proba).sum()
+    }
+
+    pub fn destroyed_probability(&self) -> f64 {
+        self.health.iter().filter(|(health, _)| health.is_destroyed()).map(|(_, proba)| proba).sum()
+    }
+}
+
+/// Rules of the sequence rolled before the allocation.
+///
+/// Damage and wards are rolled wound by wound during the allocation, so the rules of the damages
+/// and ward stages, such as `DamagesRule` and `WardRule`, are skipped, see `allocation_damages`.
+fn rolls_before_allocation(sequence: &[Box<dyn Rule>]) -> impl Iterator<Item = &dyn Rule> {
+    sequence.iter().map(|rule| rule.as_ref()).filter(|rule| !is_rolled_during_allocation(*rule))
+}
+
+fn is_rolled_during_allocation(rule: &dyn Rule) -> bool {
+    matches!(rule.stage(), Some(RuleStage::Damages | RuleStage::Ward))
+}
+
+/// Damage inflicted by a single unsaved wound, critical wound and mortal wound.
+///
+/// The damages and ward stages of the sequence are applied to a single wound, so that wards are
+/// only rolled if the sequence rolls them, and conditional rules are checked as in `compute_damages`.
+/// Without a damages stage, damage is still rolled by a `DamagesRule`.
+pub fn allocation_damages(config: &CombatConfig, sequence: &[Box<dyn Rule>]) -> [Distribution<u32>; 3] {
+    let mut rules: Vec<&dyn Rule> = sequence.iter().map(|rule| rule.as_ref()).filter(|rule| is_rolled_during_allocation(*rule)).collect();
+    if !rules.iter().any(|rule| rule.stage() == Some(RuleStage::Damages)) {
+        rules.insert(0, &DamagesRule);
+    }
+    let single_wounds = [
+        CombatStatus::new().with_wounds(1),
+        CombatStatus::new().with_critical_wounds(1),
+        CombatStatus::new().with_mortal_wounds(1),
+    ];
+    single_wounds.map(|status| {
+        rules.iter()
+            .fold(Distribution::certain(status), |states, rule| states.and_then(|status| rule.apply(status, config)))
+            .map(CombatStatus::total_damages)
+    })
+}
+
+/// Allocates the unsaved wounds of an attack sequence to a target unit.
+///
+/// Damage and wards are rolled wound by wound during the allocation, following the damages and
+/// ward stages of the sequence. Wounds are allocated first, then critical wounds and mortal wounds.
+pub fn compute_kills(config: CombatConfig, sequence: &Vec<Box<dyn Rule>>, target: TargetUnit) -> WoundAllocation {
+    WoundAllocation::new(target).allocate_attack(config, sequence)
+}
+
+/// Same as `compute_kills`, the keywords and abilities of the target applying to the attack.
+pub fn compute_kills_against(config: &CombatConfig, sequence: &Vec<Box<dyn Rule>>, target: &TargetProfile) -> WoundAllocation {
+    compute_kills(config.against(target), sequence, target.target_unit())
+}
+
+/// Health of a unit after a single simulated attack sequence, see `compute_kills`.
+///
+/// As with `WoundAllocation::allocate_attack`, a known number of target models is updated with
+/// the models still alive.
+pub fn sample_kills(
+    config: &CombatConfig,
+    sequence: &Vec<Box<dyn Rule>>,
+    health: UnitHealth,
+    wounds_per_model: u32,
+    rng: &mut dyn RngCore,
+) -> UnitHealth {
+    let config = match config.context.target_models {
+        Some(_) => config.with_context(config.context.with_target_models(health.models)),
+        None => config.clone(),
+    };
+    let status = rolls_before_allocation(sequence).fold(CombatStatus::new(), |status, rule| rule.sample(&status, &config, rng));
+    let [damages, critical_damages, mortal_damages] = allocation_damages(&config, sequence);
+    let streams = [
+        (status.wounds, damages),
+        (status.critical_wounds, critical_damages),
+        (status.mortal_wounds, mortal_damages),
+    ];
+    let mut health = health;
+    for (wounds, damages) in streams {
+        for _ in 0..wounds {
+            health = health.allocate(damages.sample(rng).unwrap_or(0), wounds_per_model);
+        }
+    }
+    health
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probabilities::combat_stats::{AttackStats, Characteristic, DefenseStats, NO_SAVE};
+    use crate::probabilities::combat_tree::{CombatContext, SequenceBuilder, compute_damages};
+    use crate::probabilities::conditions::{Condition, ConditionalRule, ConfigChange};
+    use crate::probabilities::rules::{AttackCharacteristicRule, DamagesRule, HitRule, SaveRule, WardRule, WoundRule};
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    fn config() -> CombatConfig {
+        let attack_stats = AttackStats::new(Characteristic::Value(10), 3, 4, 0, Characteristic::Value(2));
+        CombatConfig::new(attack_stats, DefenseStats::new(NO_SAVE, None))
+    }
+
+    #[test]
+    fn damages_and_ward_stages_are_skipped() {
+        let target = TargetUnit::new(5, 2);
+        let with_damages = compute_kills(config(), &SequenceBuilder::new().build(), target).models_slain();
+        let without_damages = compute_kills(config(), &SequenceBuilder::new().with_damages(false).build(), target).models_slain();
+        assert!(with_damages.mean() > 2.0);
+        assert!((with_damages.mean() - without_damages.mean()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn models_already_slain_are_not_counted() {
+        let target = TargetUnit::new(5, 2).with_damage_taken(2);
+        let allocation = WoundAllocation::new(target);
+        assert_eq!(allocation.models_slain().values_and_probas(), vec![(0, 1.0)]);
+        let allocation = allocation.allocate(&Distribution::certain(1), &Distribution::certain(2));
+        assert_eq!(allocation.models_slain().values_and_probas(), vec![(1, 1.0)]);
+    }
+
+    #[test]
+    fn conditional_damage_applies_during_allocation() {
+        let extra_damage = ConditionalRule::modified_if(
+            Condition::Flag("charged".to_string()), Box::new(DamagesRule), vec![ConfigChange::ExtraDamage(1)]
+        );
+        let sequence: Vec<Box<dyn Rule>> = vec![
+            Box::new(AttackCharacteristicRule), Box::new(HitRule), Box::new(WoundRule), Box::new(SaveRule), Box::new(extra_damage),
+        ];
+        let base = CombatConfig::new(
+            AttackStats::new(Characteristic::Value(10), 3, 4, 0, Characteristic::Value(1)),
+            DefenseStats::new(NO_SAVE, None),
+        );
+        let target = TargetUnit::new(5, 2);
+        let charged = base.with_context(CombatContext::new().with_flag("charged"));
+        let mean = compute_kills(charged, &sequence, target).models_slain().mean();
+        assert!((mean - compute_kills(config(), &sequence, target).models_slain().mean()).abs() < 1e-9);
+        assert!(mean > compute_kills(base, &sequence, target).models_slain().mean());
+    }
+
+    #[test]
+    fn target_models_follow_the_allocation() {
+        let big_targets_only = ConditionalRule::enabled_if(Condition::TargetModelsAtLeast(5), Box::new(HitRule));
+        let sequence: Vec<Box<dyn Rule>> = vec![
+            Box::new(AttackCharacteristicRule), Box::new(big_targets_only), Box::new(WoundRule), Box::new(SaveRule),
+        ];
+        let config = config().with_context(CombatContext::new().with_target_models(5));
+        let allocation = WoundAllocation::new(TargetUnit::new(5, 1))
+            .allocate(&Distribution::certain(1), &Distribution::certain(1))
+            .allocate_attack(config.clone(), &sequence);
+        assert_eq!(allocation.models_slain().values_and_probas(), vec![(1, 1.0)]);
+
+        let health = UnitHealth {models: 4, current_wounds: 1};
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        assert_eq!(sample_kills(&config, &sequence, health, 1, &mut rng), health);
+    }
+
+    // Single wound models, each damage point slaying a model as long as some are left
+    fn warded_kills_and_damages(sequence: &Vec<Box<dyn Rule>>, context: CombatContext) -> (f64, f64) {
+        let attack_stats = AttackStats::new(Characteristic::Value(10), 3, 4, 0, Characteristic::Value(1));
+        let config = CombatConfig::new(attack_stats, DefenseStats::new(NO_SAVE, Some(4))).with_context(context);
+        let kills = compute_kills(config.clone(), sequence, TargetUnit::new(20, 1)).models_slain().mean();
+        (kills, compute_damages(config, sequence).mean())
+    }
+
+    #[test]
+    fn wards_are_rolled_only_if_the_sequence_rolls_them() {
+        let (warded_kills, warded_damages) = warded_kills_and_damages(&SequenceBuilder::new().build(), CombatContext::new());
+        let (kills, damages) = warded_kills_and_damages(&SequenceBuilder::new().with_ward(false).build(), CombatContext::new());
+        assert!((warded_kills - warded_damages).abs() < 1e-9);
+        assert!((kills - damages).abs() < 1e-9);
+        assert!((warded_kills - kills / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn conditional_wards_are_checked_during_allocation() {
+        let not_monster = Condition::Not(Box::new(Condition::TargetKeyword("MONSTER".to_string())));
+        let sequence: Vec<Box<dyn Rule>> = vec![
+            Box::new(AttackCharacteristicRule), Box::new(HitRule), Box::new(WoundRule), Box::new(SaveRule),
+            Box::new(DamagesRule), Box::new(ConditionalRule::enabled_if(not_monster, Box::new(WardRule))),
+        ];
+        let (kills, damages) = warded_kills_and_damages(&sequence, CombatContext::new().with_target_keyword("MONSTER"));
+        let (unwarded_kills, _) = warded_kills_and_damages(&SequenceBuilder::new().with_ward(false).build(), CombatContext::new());
+        assert!((kills - damages).abs() < 1e-9);
+        assert!((kills - unwarded_kills).abs() < 1e-9);
+
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let config = CombatConfig::new(
+            AttackStats::new(Characteristic::Value(1), 2, 2, 0, Characteristic::Value(1)),
+            DefenseStats::new(NO_SAVE, Some(2)),
+        ).with_context(CombatContext::new().with_target_keyword("MONSTER"));
+        let health = UnitHealth {models: 1, current_wounds: 1};
+        let samples = 10000;
+        let slain = (0..samples).filter(|_| sample_kills(&config, &sequence, health, 1, &mut rng).is_destroyed()).count();
+        assert!((slain as f64 / samples as f64 - 25.0 / 36.0).abs() < 0.02);
+    }
+}
@@ -0,0 +1,118 @@
+use pyo3::prelude::*;
+
+use crate::probabilities::attacking_unit::{AttackingUnit, WeaponProfile};
+
+use super::rules::{extract_sequence, run_rules};
+use super::combat_stats::{AttackStatsPy, DefenseStatsPy, RerollsPy, RollModifierPy, TargetProfilePy};
+use super::combat_tree::CombatContextPy;
+
+// Rules are not clonable, so the Python rule objects are kept and extracted on each computation
+#[pyclass(name="WeaponProfile")]
+#[derive(Clone, Debug)]
+pub struct WeaponProfilePy {
+    pub attack_stats: AttackStatsPy,
+    pub sequence: Vec<PyObject>,
+    pub roll_modifier: Option<RollModifierPy>,
+    pub rerolls: Option<RerollsPy>,
+    pub context: Option<CombatContextPy>,
+}
+
+#[pymethods]
+impl WeaponProfilePy {
+    #[new]
+    #[pyo3(signature = (attack_stats, sequence, roll_modifier=None, rerolls=None, context=None))]
+    fn new(
+        attack_stats: AttackStatsPy,
+        sequence: Vec<&PyAny>,
+        roll_modifier: Option<RollModifierPy>,
+        rerolls: Option<RerollsPy>,
+        context: Option<CombatContextPy>
+    ) -> PyResult<Self> {
+        // Fail early on an invalid sequence
+        extract_sequence(sequence.clone())?;
+        Ok(WeaponProfilePy {
+            attack_stats,
+            sequence: sequence.into_iter().map(|rule| rule.into()).collect(),
+            roll_modifier,
+            rerolls,
+            context,
+        })
+    }
+
+    /// Models using the weapon, as given by the attack stats.
+    #[getter]
+    fn models(&self) -> u32 {self.attack_stats.attack_stats.models}
+}
+
+impl WeaponProfilePy {
+    fn weapon_profile(&self, py: Python) -> PyResult<WeaponProfile> {
+        let sequence = extract_sequence(self.sequence.iter().map(|rule| rule.as_ref(py)).collect())?;
+        let mut profile = WeaponProfile::new(self.attack_stats.attack_stats.clone(), sequence);
+        if let Some(modifier) = &self.roll_modifier {
+            profile = profile.with_modifier(modifier.roll_modifier);
+        }
+        if let Some(rerolls) = &self.rerolls {
+            profile = profile.with_rerolls(rerolls.clone().into());
+        }
+        if let Some(context) = &self.context {
+            profile = profile.with_context(context.context.clone());
+        }
+        Ok(profile)
+    }
+}
+
+#[pyclass(name="AttackingUnit")]
+#[derive(Clone, Debug)]
+pub struct AttackingUnitPy {
+    pub profiles: Vec<WeaponProfilePy>,
+}
+
+#[pymethods]
+impl AttackingUnitPy {
+    #[new]
+    fn new(profiles: Vec<WeaponProfilePy>) -> Self {
+        AttackingUnitPy {profiles}
+    }
+
+    fn add_profile(&mut self, profile: WeaponProfilePy) {
+        self.profiles.push(profile);
+    }
+
+    fn profile_damages(&self, py: Python, defense_stats: DefenseStatsPy) -> PyResult<Vec<Vec<(u32, f64)>>> {
+        let unit = self.attacking_unit(py)?;
+        run_rules(|| unit.profile_damages(&defense_stats.defense_stats)
+            .iter()
+            .map(|damages| damages.values_and_probas())
+            .collect())
+    }
+
+    /// Damage inflicted by each profile, the keywords and abilities of the target applying.
+    fn profile_damages_against(&self, py: Python, target: TargetProfilePy) -> PyResult<Vec<Vec<(u32, f64)>>> {
+        let unit = self.attacking_unit(py)?;
+        run_rules(|| unit.profile_damages_against(&target.target_profile)
+            .iter()
+            .map(|damages| damages.values_and_probas())
+            .collect())
+    }
+
+    /// Damage inflicted by the whole unit, the profiles being rolled independently.
+    fn damages(&self, py: Python, defense_stats: DefenseStatsPy) -> PyResult<Vec<(u32, f64)>> {
+        let unit = self.attacking_unit(py)?;
+        run_rules(|| unit.compute_damages(&defense_stats.defense_stats).values_and_probas())
+    }
+
+    fn damages_against(&self, py: Python, target: TargetProfilePy) -> PyResult<Vec<(u32, f64)>> {
+        let unit = self.attacking_unit(py)?;
+        run_rules(|| unit.compute_damages_against(&target.target_profile).values_and_probas())
+    }
+}
+
+impl AttackingUnitPy {
+    fn attacking_unit(&self, py: Python) -> PyResult<AttackingUnit> {
+        let profiles = self.profiles
+            .iter()
+            .map(|profile| profile.weapon_profile(py))
+            .collect::<PyResult<Vec<WeaponProfile>>>()?;
+        Ok(AttackingUnit::new(profiles))
+    }
+}
@@ -0,0 +1,62 @@
+use pyo3::prelude::*;
+
+use crate::probabilities::battle::{Battle, BattleResult, compute_battle, simulate_battle};
+use crate::probabilities::duel::Duel;
+
+use super::duel::{CombatantPy, DuelResultPy};
+use super::rules::run_rules;
+
+
+#[pyclass(name="BattleResult")]
+#[derive(Clone, Debug)]
+pub struct BattleResultPy {
+    pub result: BattleResult
+}
+
+#[pymethods]
+impl BattleResultPy {
+    /// Joint health of both units at the end of the battle.
+    #[getter]
+    fn outcome(&self) -> DuelResultPy {DuelResultPy {result: self.result.outcome.clone()}}
+
+    fn rounds(&self) -> Vec<(u32, f64)> {
+        self.result.rounds.values_and_probas()
+    }
+
+    fn first_wins_probability(&self) -> f64 {
+        self.result.first_wins_probability()
+    }
+
+    fn second_wins_probability(&self) -> f64 {
+        self.result.second_wins_probability()
+    }
+
+    fn unresolved_probability(&self) -> f64 {
+        self.result.unresolved_probability()
+    }
+}
+
+fn battle(py: Python, first: CombatantPy, second: CombatantPy, max_rounds: u32) -> PyResult<Battle> {
+    Ok(Battle::new(Duel::new(first.combatant(py)?, second.combatant(py)?), max_rounds))
+}
+
+#[pyfunction(name="compute_battle")]
+#[pyo3(signature = (first, second, max_rounds=5))]
+pub fn compute_battle_py(py: Python, first: CombatantPy, second: CombatantPy, max_rounds: u32) -> PyResult<BattleResultPy> {
+    let battle = battle(py, first, second, max_rounds)?;
+    run_rules(|| BattleResultPy {result: compute_battle(&battle)})
+}
+
+#[pyfunction(name="simulate_battle")]
+#[pyo3(signature = (first, second, samples, max_rounds=5, seed=0))]
+pub fn simulate_battle_py(
+    py: Python,
+    first: CombatantPy,
+    second: CombatantPy,
+    samples: u32,
+    max_rounds: u32,
+    seed: u64
+) -> PyResult<BattleResultPy> {
+    let battle = battle(py, first, second, max_rounds)?;
+    run_rules(|| BattleResultPy {result: simulate_battle(&battle, samples, seed)})
+}
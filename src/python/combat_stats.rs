@@ -1,8 +1,11 @@
-use pyo3::{exceptions::PyValueError, prelude::*};
+use pyo3::prelude::*;
 use crate::probabilities::combat_stats::{
-    AttackStats, Characteristic, DefenseStats, RollModifier
+    AttackStats, Characteristic, CriticalBonus, DamageReduction, CriticalThreshold, CriticalTrigger, DefenseStats, DefensiveAbility, NO_SAVE, Reroll, Rerolls, RollModifier, TargetProfile, TargetUnit, Ward, WardScope
 };
 use crate::probabilities::dice::DiceRoll;
+use crate::probabilities::errors::StatsError;
+
+use super::profiles::{from_json, to_json};
 
 
 
@@ -28,7 +31,7 @@ impl TryFrom<&PyAny> for Characteristic {
  */
 
 #[pyclass(name="Characteristic")]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct CharacteristicPy {
     pub characteristic: Characteristic 
 }
@@ -37,20 +40,7 @@ pub struct CharacteristicPy {
 impl CharacteristicPy {
     #[new]
     fn new(value: &PyAny) -> PyResult<Self> {
-        if let Ok(char_val) = value.extract::<i32>() {Ok(
-            CharacteristicPy { characteristic: Characteristic::Value(char_val as u32)}
-        )}
-        else if let Ok(char_roll) = TryInto::try_into(value) {Ok(
-            CharacteristicPy {
-                characteristic: Characteristic::DiceRoll(char_roll)
-            }
-        )}
-        else if let Ok(dice_str) = value.extract::<String>() {Ok(
-            CharacteristicPy{
-                characteristic: Characteristic::DiceRoll(DiceRoll::from_str(dice_str).unwrap())
-            }
-        )}
-        else {Err(PyValueError::new_err("Could not convert to Characteritic"))}
+        Ok(CharacteristicPy {characteristic: extract_characteristic(value)?})
     }
 }
 
@@ -72,27 +62,99 @@ pub struct AttackStatsPy {
     pub attack_stats: AttackStats
 }
 
-fn extract_characteristic(value: &PyAny) -> Characteristic {
-    if let Ok(char_val) = value.extract::<i32>() {Characteristic::Value(char_val as u32)}
-    else if let Ok(dice_str) = value.extract::<String>() {Characteristic::DiceRoll(DiceRoll::from_str(dice_str).unwrap())}
-    else if let Ok(char_roll) = TryInto::try_into(value) {Characteristic::DiceRoll(char_roll)}
-    else if let Ok(charac) = value.extract::<CharacteristicPy>() {charac.into()}
-    else {panic!("Could not convert to Characteritic")}
+fn extract_characteristic(value: &PyAny) -> Result<Characteristic, StatsError> {
+    if let Ok(char_val) = value.extract::<i32>() {Ok(Characteristic::Value(non_negative("characteristic", char_val)?))}
+    else if let Ok(dice_str) = value.extract::<String>() {Ok(Characteristic::DiceRoll(DiceRoll::from_str(dice_str)?))}
+    else if let Ok(char_roll) = TryInto::try_into(value) {Ok(Characteristic::DiceRoll(char_roll))}
+    else if let Ok(charac) = value.extract::<CharacteristicPy>() {Ok(charac.into())}
+    else {Err(StatsError::InvalidStats(format!("Could not convert {} to a characteristic", value)))}
+}
+
+fn non_negative(name: &str, value: i32) -> Result<u32, StatsError> {
+    u32::try_from(value).map_err(|_| StatsError::InvalidStats(format!("{} must be non negative, got {}", name, value)))
 }
 
 #[pymethods]
 impl AttackStatsPy {
     #[new]
-    fn new(attacks: &PyAny, to_hit: i32, to_wound: i32, rend: i32, damages: &PyAny) -> Self {
-        AttackStatsPy {
-            attack_stats: AttackStats {
-                attacks: extract_characteristic(attacks).into(),
-                to_hit: to_hit as u32,
-                to_wound: to_wound as u32,
-                rend: rend as u32,
-                damages : extract_characteristic(damages).into()
-            }
-        }
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (attacks, to_hit, to_wound, rend, damages, critical_hit=6, critical_wound=None, critical_after_modifiers=false, critical_bonus=None, mortal_damages=None, models=1, champion_attacks=0))]
+    fn new(
+        attacks: &PyAny,
+        to_hit: i32,
+        to_wound: i32,
+        rend: i32,
+        damages: &PyAny,
+        critical_hit: u32,
+        critical_wound: Option<u32>,
+        critical_after_modifiers: bool,
+        critical_bonus: Option<CriticalBonusPy>,
+        mortal_damages: Option<&PyAny>,
+        models: u32,
+        champion_attacks: u32
+    ) -> PyResult<Self> {
+        let threshold = |on| CriticalThreshold {on, modified: critical_after_modifiers};
+        Ok(AttackStatsPy {
+            attack_stats: AttackStats::new(
+                extract_characteristic(attacks)?,
+                non_negative("to_hit", to_hit)?,
+                non_negative("to_wound", to_wound)?,
+                rend,
+                extract_characteristic(damages)?
+            )
+            .with_critical_hit(threshold(critical_hit))
+            .with_critical_wound(critical_wound.map(threshold))
+            .with_critical_bonus(critical_bonus.map(Into::into))
+            .with_mortal_damages(mortal_damages.map(extract_characteristic).transpose()?)
+            .with_models(models)
+            .with_champion_attacks(champion_attacks)
+        })
+    }
+
+    #[getter]
+    fn to_hit(&self) -> u32 {self.attack_stats.to_hit}
+
+    #[getter]
+    fn to_wound(&self) -> u32 {self.attack_stats.to_wound}
+
+    #[getter]
+    fn rend(&self) -> i32 {self.attack_stats.rend}
+
+    #[getter]
+    fn models(&self) -> u32 {self.attack_stats.models}
+
+    #[getter]
+    fn champion_attacks(&self) -> u32 {self.attack_stats.champion_attacks}
+
+    fn to_json(&self) -> PyResult<String> {
+        to_json(&self.attack_stats)
+    }
+
+    #[staticmethod]
+    fn from_json(text: &str) -> PyResult<Self> {
+        Ok(AttackStatsPy {attack_stats: from_json(text)?})
+    }
+}
+
+#[pyclass(name="CriticalBonus")]
+#[derive(Clone, Debug)]
+pub struct CriticalBonusPy {
+    pub critical_bonus: CriticalBonus
+}
+
+#[pymethods]
+impl CriticalBonusPy {
+    #[new]
+    #[pyo3(signature = (trigger, rend=0, damage=0))]
+    fn new(trigger: &str, rend: i32, damage: u32) -> PyResult<Self> {
+        let trigger = match trigger {
+            "hit" => CriticalTrigger::Hit,
+            "wound" => CriticalTrigger::Wound,
+            other => return Err(StatsError::InvalidStats(format!(
+                "Unknown critical trigger '{}', expected 'hit' or 'wound'", other
+            )).into())
+        };
+        Ok(CriticalBonusPy {critical_bonus: CriticalBonus::new(trigger, rend, damage)})
     }
 }
 
@@ -104,13 +166,83 @@ pub struct DefenseStatsPy {
 
 #[pymethods]
 impl DefenseStatsPy {
+    /// A `to_save` of `None` or 7+ means no save, `wards` are rolled after `ward`.
     #[new]
-    fn new(to_save: u32, ward: Option<u32>) -> Self {
-        DefenseStatsPy {
-            defense_stats: DefenseStats {
-                to_save, ward
-            }
-        }
+    #[pyo3(signature = (to_save, ward=None, ward_scope=None, wards=None, damage_reduction=None))]
+    fn new(
+        to_save: Option<u32>,
+        ward: Option<u32>,
+        ward_scope: Option<&str>,
+        wards: Option<Vec<WardPy>>,
+        damage_reduction: Option<DamageReductionPy>
+    ) -> PyResult<Self> {
+        let mut all_wards = match ward {
+            Some(on) => vec![Ward::new(on).with_scope(extract_ward_scope(ward_scope)?)],
+            None => vec![]
+        };
+        all_wards.extend(wards.unwrap_or_default().into_iter().map(|ward| ward.ward));
+        Ok(DefenseStatsPy {
+            defense_stats: DefenseStats::new(to_save.unwrap_or(NO_SAVE), None)
+                .with_wards(all_wards)
+                .with_damage_reduction(damage_reduction.map(|reduction| reduction.damage_reduction))
+        })
+    }
+
+    /// `None` for units without save.
+    #[getter]
+    fn to_save(&self) -> Option<u32> {
+        self.defense_stats.has_save().then_some(self.defense_stats.to_save)
+    }
+
+    fn to_json(&self) -> PyResult<String> {
+        to_json(&self.defense_stats)
+    }
+
+    #[staticmethod]
+    fn from_json(text: &str) -> PyResult<Self> {
+        Ok(DefenseStatsPy {defense_stats: from_json(text)?})
+    }
+}
+
+fn extract_ward_scope(value: Option<&str>) -> Result<WardScope, StatsError> {
+    match value {
+        None | Some("all") => Ok(WardScope::All),
+        Some("mortal_wounds") => Ok(WardScope::MortalWoundsOnly),
+        Some(other) => Err(StatsError::InvalidStats(format!(
+            "Unknown ward scope '{}', expected 'all' or 'mortal_wounds'", other
+        )))
+    }
+}
+
+#[pyclass(name="Ward")]
+#[derive(Clone, Debug)]
+pub struct WardPy {
+    pub ward: Ward
+}
+
+#[pymethods]
+impl WardPy {
+    #[new]
+    #[pyo3(signature = (on, scope=None, per_wound=false))]
+    fn new(on: u32, scope: Option<&str>, per_wound: bool) -> PyResult<Self> {
+        Ok(WardPy {
+            ward: Ward::new(on).with_scope(extract_ward_scope(scope)?).with_per_wound(per_wound)
+        })
+    }
+}
+
+#[pyclass(name="DamageReduction")]
+#[derive(Clone, Debug)]
+pub struct DamageReductionPy {
+    pub damage_reduction: DamageReduction
+}
+
+#[pymethods]
+impl DamageReductionPy {
+    #[new]
+    #[pyo3(signature = (amount, minimum=0))]
+    fn new(amount: u32, minimum: u32) -> Self {
+        DamageReductionPy {damage_reduction: DamageReduction::new(amount, minimum)}
     }
 }
 
@@ -128,6 +260,136 @@ impl RollModifierPy {
             roll_modifier: RollModifier::new(to_hit, to_wound, to_save)
         }
     }
+
+    fn to_json(&self) -> PyResult<String> {
+        to_json(&self.roll_modifier)
+    }
+
+    #[staticmethod]
+    fn from_json(text: &str) -> PyResult<Self> {
+        Ok(RollModifierPy {roll_modifier: from_json(text)?})
+    }
+}
+
+pub fn extract_reroll(value: Option<&str>) -> PyResult<Reroll> {
+    match value {
+        None | Some("none") => Ok(Reroll::None),
+        Some("ones") => Ok(Reroll::Ones),
+        Some("failures") => Ok(Reroll::Failures),
+        Some("single") => Ok(Reroll::SingleDie),
+        Some("non_critical") => Ok(Reroll::NonCritical),
+        Some(other) => Err(StatsError::InvalidStats(format!(
+            "Unknown reroll '{}', expected one of 'none', 'ones', 'failures', 'single', 'non_critical'", other
+        )).into())
+    }
+}
+
+#[pyclass(name="Rerolls")]
+#[derive(Clone, Debug)]
+pub struct RerollsPy {
+    pub rerolls: Rerolls
+}
+
+#[pymethods]
+impl RerollsPy {
+    #[new]
+    fn new(hit: Option<&str>, wound: Option<&str>, save: Option<&str>) -> PyResult<Self> {
+        Ok(RerollsPy {
+            rerolls: Rerolls::new(extract_reroll(hit)?, extract_reroll(wound)?, extract_reroll(save)?)
+        })
+    }
+
+    fn to_json(&self) -> PyResult<String> {
+        to_json(&self.rerolls)
+    }
+
+    #[staticmethod]
+    fn from_json(text: &str) -> PyResult<Self> {
+        Ok(RerollsPy {rerolls: from_json(text)?})
+    }
+}
+
+#[pyclass(name="TargetUnit")]
+#[derive(Clone, Debug)]
+pub struct TargetUnitPy {
+    pub target_unit: TargetUnit
+}
+
+#[pymethods]
+impl TargetUnitPy {
+    #[new]
+    fn new(models: u32, wounds_per_model: u32, damage_taken: Option<u32>) -> Self {
+        TargetUnitPy {
+            target_unit: TargetUnit::new(models, wounds_per_model).with_damage_taken(damage_taken.unwrap_or(0))
+        }
+    }
+}
+
+/// Target unit with its keywords and the abilities changing the attacks made against it.
+#[pyclass(name="TargetProfile")]
+#[derive(Clone, Debug)]
+pub struct TargetProfilePy {
+    pub target_profile: TargetProfile
+}
+
+#[pymethods]
+impl TargetProfilePy {
+    #[new]
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (defense_stats, models, wounds_per_model, keywords=None, hit_modifier=0, wound_modifier=0, save_modifier=0, rend_reduction=0))]
+    fn new(
+        defense_stats: DefenseStatsPy,
+        models: u32,
+        wounds_per_model: u32,
+        keywords: Option<Vec<String>>,
+        hit_modifier: i32,
+        wound_modifier: i32,
+        save_modifier: i32,
+        rend_reduction: u32
+    ) -> Self {
+        let mut target_profile = TargetProfile::new(defense_stats.defense_stats, models, wounds_per_model);
+        for keyword in keywords.unwrap_or_default() {
+            target_profile = target_profile.with_keyword(&keyword);
+        }
+        let abilities = [
+            (hit_modifier != 0, DefensiveAbility::HitModifier(hit_modifier)),
+            (wound_modifier != 0, DefensiveAbility::WoundModifier(wound_modifier)),
+            (save_modifier != 0, DefensiveAbility::SaveModifier(save_modifier)),
+            (rend_reduction != 0, DefensiveAbility::RendReduction(rend_reduction)),
+        ];
+        for (_, ability) in abilities.into_iter().filter(|(active, _)| *active) {
+            target_profile = target_profile.with_ability(ability);
+        }
+        TargetProfilePy {target_profile}
+    }
+
+    #[getter]
+    fn defense_stats(&self) -> DefenseStatsPy {DefenseStatsPy {defense_stats: self.target_profile.defense_stats.clone()}}
+
+    #[getter]
+    fn models(&self) -> u32 {self.target_profile.models}
+
+    #[getter]
+    fn wounds_per_model(&self) -> u32 {self.target_profile.wounds_per_model}
+
+    #[getter]
+    fn keywords(&self) -> Vec<String> {self.target_profile.keywords.iter().cloned().collect()}
+
+    #[getter]
+    fn target_unit(&self) -> TargetUnitPy {TargetUnitPy {target_unit: self.target_profile.target_unit()}}
+
+    fn has_keyword(&self, keyword: &str) -> bool {
+        self.target_profile.has_keyword(keyword)
+    }
+
+    fn to_json(&self) -> PyResult<String> {
+        to_json(&self.target_profile)
+    }
+
+    #[staticmethod]
+    fn from_json(text: &str) -> PyResult<Self> {
+        Ok(TargetProfilePy {target_profile: from_json(text)?})
+    }
 }
 
 impl Into<AttackStats> for AttackStatsPy {
@@ -146,4 +408,22 @@ impl Into<RollModifier> for RollModifierPy {
     fn into(self) -> RollModifier {
         self.roll_modifier
     }
-}
\ No newline at end of file
+}
+
+impl From<TargetUnitPy> for TargetUnit {
+    fn from(target: TargetUnitPy) -> TargetUnit {
+        target.target_unit
+    }
+}
+
+impl From<CriticalBonusPy> for CriticalBonus {
+    fn from(bonus: CriticalBonusPy) -> CriticalBonus {
+        bonus.critical_bonus
+    }
+}
+
+impl From<RerollsPy> for Rerolls {
+    fn from(rerolls: RerollsPy) -> Rerolls {
+        rerolls.rerolls
+    }
+}
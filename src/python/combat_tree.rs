@@ -2,13 +2,61 @@ use pyo3::prelude::*;
 
 
 use crate::probabilities::combat_tree::{
-    CombatConfig, compute_damages, Rule
+    CombatConfig, CombatContext, CombatStatus, compute_damages, compute_damages_summary
 };
+use crate::probabilities::statistics::DistributionSummary;
 
+use super::rules::{extract_sequence, run_rules};
 use super::combat_stats::{
-    AttackStatsPy, DefenseStatsPy, RollModifierPy
+    AttackStatsPy, DefenseStatsPy, RerollsPy, RollModifierPy, TargetProfilePy
 };
 
+/// Game state checked by conditional rules.
+#[pyclass(name="CombatContext")]
+#[derive(Clone, Debug)]
+pub struct CombatContextPy {
+    pub context: CombatContext
+}
+
+#[pymethods]
+impl CombatContextPy {
+    #[new]
+    #[pyo3(signature = (flags=None, attacker_keywords=None, target_keywords=None, target_models=None))]
+    fn new(
+        flags: Option<Vec<String>>,
+        attacker_keywords: Option<Vec<String>>,
+        target_keywords: Option<Vec<String>>,
+        target_models: Option<u32>
+    ) -> Self {
+        let mut context = CombatContext::new();
+        for flag in flags.unwrap_or_default() {
+            context = context.with_flag(&flag);
+        }
+        for keyword in attacker_keywords.unwrap_or_default() {
+            context = context.with_attacker_keyword(&keyword);
+        }
+        for keyword in target_keywords.unwrap_or_default() {
+            context = context.with_target_keyword(&keyword);
+        }
+        if let Some(models) = target_models {
+            context = context.with_target_models(models);
+        }
+        CombatContextPy {context}
+    }
+
+    #[getter]
+    fn flags(&self) -> Vec<String> {self.context.flags.iter().cloned().collect()}
+
+    #[getter]
+    fn attacker_keywords(&self) -> Vec<String> {self.context.attacker_keywords.iter().cloned().collect()}
+
+    #[getter]
+    fn target_keywords(&self) -> Vec<String> {self.context.target_keywords.iter().cloned().collect()}
+
+    #[getter]
+    fn target_models(&self) -> Option<u32> {self.context.target_models}
+}
+
 #[pyclass(name="CombatConfig")]
 #[derive(Clone, Debug)]
 pub struct CombatConfigPy {
@@ -18,23 +66,46 @@ pub struct CombatConfigPy {
 #[pymethods]
 impl CombatConfigPy {
     #[new]
+    #[pyo3(signature = (attack_stats, defense_stats, roll_modifier=None, rerolls=None, context=None))]
     fn new(
         attack_stats: AttackStatsPy,
         defense_stats: DefenseStatsPy,
-        roll_modifier: Option<RollModifierPy>
+        roll_modifier: Option<RollModifierPy>,
+        rerolls: Option<RerollsPy>,
+        context: Option<CombatContextPy>
     ) -> Self {
 
-        if let Some(modifier) = roll_modifier {
-            CombatConfigPy {
-                config: CombatConfig::new_with_modifiers(attack_stats.attack_stats, defense_stats.defense_stats, modifier.roll_modifier)
-            }
+        let config = if let Some(modifier) = roll_modifier {
+            CombatConfig::new_with_modifiers(attack_stats.attack_stats, defense_stats.defense_stats, modifier.roll_modifier)
         }
         else {
-            CombatConfigPy {
-                config: CombatConfig::new(attack_stats.attack_stats, defense_stats.defense_stats)
-            }
+            CombatConfig::new(attack_stats.attack_stats, defense_stats.defense_stats)
+        };
+
+        let config = match rerolls {
+            Some(rerolls) => config.with_rerolls(rerolls.into()),
+            None => config
+        };
+
+        match context {
+            Some(context) => CombatConfigPy {config: config.with_context(context.context)},
+            None => CombatConfigPy {config}
         }
     }
+
+    #[getter]
+    fn attack_stats(&self) -> AttackStatsPy {AttackStatsPy {attack_stats: self.config.attack_stats.clone()}}
+
+    #[getter]
+    fn defense_stats(&self) -> DefenseStatsPy {DefenseStatsPy {defense_stats: self.config.defense_stats.clone()}}
+
+    #[getter]
+    fn context(&self) -> CombatContextPy {CombatContextPy {context: self.config.context.clone()}}
+
+    /// Same attack made against `target`, whose keywords and abilities apply.
+    fn against(&self, target: &TargetProfilePy) -> Self {
+        CombatConfigPy {config: self.config.against(&target.target_profile)}
+    }
 }
 
 impl Into<CombatConfig> for CombatConfigPy {
@@ -44,10 +115,168 @@ impl Into<CombatConfig> for CombatConfigPy {
 }
 
 
+/// State of a combat, as handled by the rules.
+#[pyclass(name="CombatStatus")]
+#[derive(Clone, Debug)]
+pub struct CombatStatusPy {
+    pub status: CombatStatus
+}
+
+#[pymethods]
+impl CombatStatusPy {
+    #[new]
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (
+        attacks=0, hits=0, critical_hits=0, wounds=0, critical_wounds=0, mortal_wounds=0, damages=0, mortal_damages=0,
+        warded_damages=0
+    ))]
+    fn new(
+        attacks: u32,
+        hits: u32,
+        critical_hits: u32,
+        wounds: u32,
+        critical_wounds: u32,
+        mortal_wounds: u32,
+        damages: u32,
+        mortal_damages: u32,
+        warded_damages: u32
+    ) -> Self {
+        CombatStatusPy {
+            status: CombatStatus {
+                attacks, hits, critical_hits, wounds, critical_wounds, mortal_wounds, damages, mortal_damages, warded_damages
+            }
+        }
+    }
+
+    #[getter]
+    fn attacks(&self) -> u32 {self.status.attacks}
+
+    #[getter]
+    fn hits(&self) -> u32 {self.status.hits}
+
+    #[getter]
+    fn critical_hits(&self) -> u32 {self.status.critical_hits}
+
+    #[getter]
+    fn wounds(&self) -> u32 {self.status.wounds}
+
+    #[getter]
+    fn critical_wounds(&self) -> u32 {self.status.critical_wounds}
+
+    #[getter]
+    fn mortal_wounds(&self) -> u32 {self.status.mortal_wounds}
+
+    #[getter]
+    fn damages(&self) -> u32 {self.status.damages}
+
+    #[getter]
+    fn mortal_damages(&self) -> u32 {self.status.mortal_damages}
+
+    #[getter]
+    fn warded_damages(&self) -> u32 {self.status.warded_damages}
+
+    fn with_attacks(&self, value: u32) -> Self {
+        CombatStatusPy {status: self.status.with_attacks(value)}
+    }
+
+    fn with_hits(&self, value: u32) -> Self {
+        CombatStatusPy {status: self.status.with_hits(value)}
+    }
+
+    fn with_critical_hits(&self, value: u32) -> Self {
+        CombatStatusPy {status: self.status.with_critical_hits(value)}
+    }
+
+    fn with_wounds(&self, value: u32) -> Self {
+        CombatStatusPy {status: self.status.with_wounds(value)}
+    }
+
+    fn with_critical_wounds(&self, value: u32) -> Self {
+        CombatStatusPy {status: self.status.with_critical_wounds(value)}
+    }
+
+    fn with_mortal_wounds(&self, value: u32) -> Self {
+        CombatStatusPy {status: self.status.with_mortal_wounds(value)}
+    }
+
+    fn with_damages(&self, value: u32) -> Self {
+        CombatStatusPy {status: self.status.with_damages(value)}
+    }
+
+    fn with_mortal_damages(&self, value: u32) -> Self {
+        CombatStatusPy {status: self.status.with_mortal_damages(value)}
+    }
+
+    fn with_warded_damages(&self, value: u32) -> Self {
+        CombatStatusPy {status: self.status.with_warded_damages(value)}
+    }
+
+    fn total_damages(&self) -> u32 {
+        self.status.total_damages()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.status)
+    }
+
+    fn __eq__(&self, other: &Self) -> bool {
+        self.status == other.status
+    }
+}
+
+
 #[pyfunction(name="compute_damages")]
-pub fn compute_damages_py(config: CombatConfigPy, sequence: Vec<&PyAny>) -> Vec<(u32, f64)> {
-    let rule_sequence: Vec<Box<dyn Rule>> = sequence.iter().map(|rule| Into::<Box<dyn Rule>>::into(*rule)).collect();
-    compute_damages(config.into(), &rule_sequence)
+pub fn compute_damages_py(config: CombatConfigPy, sequence: Vec<&PyAny>) -> PyResult<Vec<(u32, f64)>> {
+    let rule_sequence = extract_sequence(sequence)?;
+    run_rules(|| compute_damages(config.into(), &rule_sequence).values_and_probas())
+}
+
+#[pyclass(name="DistributionSummary")]
+#[derive(Clone, Debug)]
+pub struct DistributionSummaryPy {
+    pub summary: DistributionSummary
 }
 
+#[pymethods]
+impl DistributionSummaryPy {
+    #[getter]
+    fn mean(&self) -> f64 {self.summary.mean}
+
+    #[getter]
+    fn variance(&self) -> f64 {self.summary.variance}
+
+    #[getter]
+    fn std_dev(&self) -> f64 {self.summary.std_dev}
+
+    #[getter]
+    fn median(&self) -> u32 {self.summary.median}
+
+    #[getter]
+    fn mode(&self) -> u32 {self.summary.mode}
 
+    #[getter]
+    fn cdf(&self) -> Vec<(u32, f64)> {self.summary.cdf.clone()}
+
+    #[getter]
+    fn survival(&self) -> Vec<(u32, f64)> {self.summary.survival.clone()}
+
+    fn quantile(&self, q: f64) -> PyResult<u32> {
+        Ok(self.summary.quantile(q)?)
+    }
+
+    fn probability_at_least(&self, k: u32) -> f64 {
+        self.summary.probability_at_least(k)
+    }
+}
+
+impl From<DistributionSummary> for DistributionSummaryPy {
+    fn from(summary: DistributionSummary) -> Self {
+        DistributionSummaryPy {summary}
+    }
+}
+
+#[pyfunction(name="compute_damages_summary")]
+pub fn compute_damages_summary_py(config: CombatConfigPy, sequence: Vec<&PyAny>) -> PyResult<DistributionSummaryPy> {
+    let rule_sequence = extract_sequence(sequence)?;
+    run_rules(|| compute_damages_summary(config.into(), &rule_sequence).into())
+}
@@ -0,0 +1,144 @@
+use pyo3::prelude::*;
+
+use crate::probabilities::combat_tree::Rule;
+use crate::probabilities::conditions::{Condition, ConditionalMode, ConditionalRule, ConfigChange};
+use crate::probabilities::errors::StatsError;
+
+use super::combat_stats::extract_reroll;
+use super::rules::SaveRulePy;
+
+
+/// Condition on the game state, combined with `&`, `|` and `~`.
+#[pyclass(name="Condition")]
+#[derive(Clone, Debug)]
+pub struct ConditionPy {
+    pub condition: Condition
+}
+
+#[pymethods]
+impl ConditionPy {
+    #[staticmethod]
+    fn flag(flag: String) -> Self {
+        ConditionPy {condition: Condition::Flag(flag)}
+    }
+
+    #[staticmethod]
+    fn attacker_keyword(keyword: String) -> Self {
+        ConditionPy {condition: Condition::AttackerKeyword(keyword)}
+    }
+
+    #[staticmethod]
+    fn target_keyword(keyword: String) -> Self {
+        ConditionPy {condition: Condition::TargetKeyword(keyword)}
+    }
+
+    #[staticmethod]
+    fn target_models_at_least(models: u32) -> Self {
+        ConditionPy {condition: Condition::TargetModelsAtLeast(models)}
+    }
+
+    fn __and__(&self, other: &Self) -> Self {
+        ConditionPy {condition: Condition::All(vec![self.condition.clone(), other.condition.clone()])}
+    }
+
+    fn __or__(&self, other: &Self) -> Self {
+        ConditionPy {condition: Condition::Any(vec![self.condition.clone(), other.condition.clone()])}
+    }
+
+    fn __invert__(&self) -> Self {
+        ConditionPy {condition: Condition::Not(Box::new(self.condition.clone()))}
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.condition)
+    }
+}
+
+// The wrapped rule is kept as a Python object, rules not being clonable
+#[pyclass(name="ConditionalRule")]
+#[derive(Clone, Debug)]
+pub struct ConditionalRulePy {
+    pub condition: ConditionPy,
+    pub rule: PyObject,
+    pub mode: ConditionalMode,
+}
+
+#[pymethods]
+impl ConditionalRulePy {
+    /// With the `"apply"` mode, the rule is only applied when the condition holds.
+    /// With the `"modify"` mode, the rule is always applied, with the changes when the condition holds.
+    #[new]
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (
+        condition, rule, mode="apply", hit_modifier=0, wound_modifier=0, save_modifier=0, rend=0,
+        extra_damage=0, extra_attacks=0, hit_reroll=None, wound_reroll=None, save_reroll=None
+    ))]
+    fn new(
+        condition: ConditionPy,
+        rule: &PyAny,
+        mode: &str,
+        hit_modifier: i32,
+        wound_modifier: i32,
+        save_modifier: i32,
+        rend: i32,
+        extra_damage: u32,
+        extra_attacks: u32,
+        hit_reroll: Option<&str>,
+        wound_reroll: Option<&str>,
+        save_reroll: Option<&str>
+    ) -> PyResult<Self> {
+        // Fail early on an object which is not a rule
+        let _: Box<dyn Rule> = TryInto::try_into(rule)?;
+        let mut changes = Vec::new();
+        if hit_modifier != 0 {changes.push(ConfigChange::HitModifier(hit_modifier));}
+        if wound_modifier != 0 {changes.push(ConfigChange::WoundModifier(wound_modifier));}
+        if save_modifier != 0 {changes.push(ConfigChange::SaveModifier(save_modifier));}
+        if rend != 0 {changes.push(ConfigChange::Rend(rend));}
+        if extra_damage != 0 {changes.push(ConfigChange::ExtraDamage(extra_damage));}
+        if extra_attacks != 0 {changes.push(ConfigChange::ExtraAttacks(extra_attacks));}
+        if hit_reroll.is_some() {changes.push(ConfigChange::HitReroll(extract_reroll(hit_reroll)?));}
+        if wound_reroll.is_some() {changes.push(ConfigChange::WoundReroll(extract_reroll(wound_reroll)?));}
+        if save_reroll.is_some() {changes.push(ConfigChange::SaveReroll(extract_reroll(save_reroll)?));}
+        let mode = match mode {
+            "apply" if changes.is_empty() => ConditionalMode::Apply,
+            "apply" => return Err(StatsError::InvalidStats("Changes require the 'modify' mode".to_string()).into()),
+            "modify" => ConditionalMode::Modify(changes),
+            other => return Err(StatsError::InvalidStats(format!(
+                "Unknown mode '{}', expected one of 'apply', 'modify'", other
+            )).into()),
+        };
+        Ok(ConditionalRulePy {condition, rule: rule.into(), mode})
+    }
+
+    /// Save rule of an Anti-X weapon, improving its rend against targets with the keyword.
+    #[staticmethod]
+    #[pyo3(signature = (keyword, rend=1))]
+    fn anti(py: Python, keyword: String, rend: i32) -> Self {
+        ConditionalRulePy {
+            condition: ConditionPy {condition: Condition::TargetKeyword(keyword)},
+            rule: SaveRulePy.into_py(py),
+            mode: ConditionalMode::Modify(vec![ConfigChange::Rend(rend)]),
+        }
+    }
+
+    #[getter]
+    fn condition(&self) -> ConditionPy {self.condition.clone()}
+
+    #[getter]
+    fn rule(&self, py: Python) -> PyObject {self.rule.clone_ref(py)}
+
+    #[getter]
+    fn mode(&self) -> &str {
+        match self.mode {
+            ConditionalMode::Apply => "apply",
+            ConditionalMode::Modify(_) => "modify",
+        }
+    }
+}
+
+impl ConditionalRulePy {
+    pub fn conditional_rule(&self, py: Python) -> Result<ConditionalRule, StatsError> {
+        let rule: Box<dyn Rule> = TryInto::try_into(self.rule.as_ref(py))?;
+        Ok(ConditionalRule::new(self.condition.condition.clone(), rule, self.mode.clone()))
+    }
+}
@@ -2,6 +2,7 @@ use pyo3::exceptions::{PyValueError, PyNotImplementedError};
 use pyo3::prelude::*;
 use pyo3::PyClass;
 use crate::probabilities::dice::DiceRoll;
+use crate::probabilities::errors::StatsError;
 
 //======================== Dice =========================
 // Base class for all dice rolls
@@ -37,7 +38,11 @@ impl DiceRollPy {
             Ok(DiceRoll::D6Plus(m)) => _wrap_type(py, D6Plus::new(m)),
             Ok(DiceRoll::ND3Plus(n, m)) => _wrap_type(py, ND3Plus::new(n, m)),
             Ok(DiceRoll::ND6Plus(n, m)) => _wrap_type(py, ND6Plus::new(n, m)),
-            Err(e) => Err(PyValueError::new_err(e.to_string()))
+            Ok(DiceRoll::Expression(expression)) => _wrap_type(
+                py,
+                (DiceExpressionPy {dice: DiceRoll::Expression(expression)}, DiceRollPy)
+            ),
+            Err(e) => Err(StatsError::from(e).into())
         }
     }
 }
@@ -93,7 +98,7 @@ impl ND6 {
     }
 
     fn values_and_probas(&self) -> PyResult<Vec<(u32, f64)>> {
-        Ok(DiceRoll::ND3(self.n).values_and_probas())
+        Ok(DiceRoll::ND6(self.n).values_and_probas())
     }
 }
 
@@ -191,6 +196,26 @@ impl ND3Plus {
     }
 }
 
+// Any expression accepted by DiceRoll.from_str, e.g. "2D6+D3-1" or "max(D6, 3)"
+#[pyclass(name="DiceExpression", extends=DiceRollPy)]
+#[derive(Clone, Debug)]
+pub struct DiceExpressionPy {
+    dice: DiceRoll
+}
+
+#[pymethods]
+impl DiceExpressionPy {
+    #[new]
+    fn new(expression: String) -> PyResult<(Self, DiceRollPy)> {
+        let dice = DiceRoll::from_str(expression).map_err(StatsError::from)?;
+        Ok((DiceExpressionPy {dice}, DiceRollPy))
+    }
+
+    fn values_and_probas(&self) -> PyResult<Vec<(u32, f64)>> {
+        Ok(self.dice.values_and_probas())
+    }
+}
+
 impl TryFrom<&PyAny> for DiceRoll {
     type Error = PyErr;
 
@@ -203,10 +228,16 @@ impl TryFrom<&PyAny> for DiceRoll {
             Ok(DiceRoll::ND3(nd3.n))
         } else if let Ok(nd6) = value.extract::<ND6>() {
             Ok(DiceRoll::ND6(nd6.n))
+        } else if let Ok(d3plus) = value.extract::<D3Plus>() {
+            Ok(DiceRoll::D3Plus(d3plus.m))
+        } else if let Ok(d6plus) = value.extract::<D6Plus>() {
+            Ok(DiceRoll::D6Plus(d6plus.m))
         } else if let Ok(nd3plus) = value.extract::<ND3Plus>() {
             Ok(DiceRoll::ND3Plus(nd3plus.n, nd3plus.m))
         } else if let Ok(nd6plus) = value.extract::<ND6Plus>() {
             Ok(DiceRoll::ND6Plus(nd6plus.n, nd6plus.m))
+        } else if let Ok(expression) = value.extract::<DiceExpressionPy>() {
+            Ok(expression.dice)
         } else {
             Err(PyErr::new::<PyValueError, _>("Not implemented for this type"))
         }
@@ -225,10 +256,16 @@ impl TryFrom<PyAny> for DiceRoll {
             Ok(DiceRoll::ND3(nd3.n))
         } else if let Ok(nd6) = value.extract::<ND6>() {
             Ok(DiceRoll::ND6(nd6.n))
+        } else if let Ok(d3plus) = value.extract::<D3Plus>() {
+            Ok(DiceRoll::D3Plus(d3plus.m))
+        } else if let Ok(d6plus) = value.extract::<D6Plus>() {
+            Ok(DiceRoll::D6Plus(d6plus.m))
         } else if let Ok(nd3plus) = value.extract::<ND3Plus>() {
             Ok(DiceRoll::ND3Plus(nd3plus.n, nd3plus.m))
         } else if let Ok(nd6plus) = value.extract::<ND6Plus>() {
             Ok(DiceRoll::ND6Plus(nd6plus.n, nd6plus.m))
+        } else if let Ok(expression) = value.extract::<DiceExpressionPy>() {
+            Ok(expression.dice)
         } else {
             Err(PyErr::new::<PyValueError, _>("Not implemented for this type"))
         }
@@ -0,0 +1,100 @@
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use crate::probabilities::distribution::Distribution;
+use crate::python::combat_tree::DistributionSummaryPy;
+
+
+#[pyclass(name="Distribution")]
+#[derive(Clone, Debug)]
+pub struct DistributionPy {
+    pub distribution: Distribution<u32>
+}
+
+#[pymethods]
+impl DistributionPy {
+    #[new]
+    fn new(values_and_probas: Vec<(u32, f64)>) -> Self {
+        DistributionPy {distribution: Distribution::new(values_and_probas)}
+    }
+
+    #[staticmethod]
+    fn certain(value: u32) -> Self {
+        DistributionPy {distribution: Distribution::certain(value)}
+    }
+
+    #[staticmethod]
+    fn mixture(components: Vec<(DistributionPy, f64)>) -> Self {
+        DistributionPy {
+            distribution: Distribution::mixture(
+                components.into_iter().map(|(component, weight)| (component.distribution, weight)).collect()
+            )
+        }
+    }
+
+    fn values_and_probas(&self) -> Vec<(u32, f64)> {
+        self.distribution.values_and_probas()
+    }
+
+    fn probability(&self, value: u32) -> f64 {
+        self.distribution.probability(&value)
+    }
+
+    fn sum_of(&self, n: u32) -> Self {
+        DistributionPy {distribution: self.distribution.sum_of(n, 0)}
+    }
+
+    fn scale(&self, factor: u32) -> Self {
+        DistributionPy {distribution: self.distribution.scale(factor)}
+    }
+
+    fn max(&self, other: &DistributionPy) -> Self {
+        DistributionPy {distribution: self.distribution.max(&other.distribution)}
+    }
+
+    fn min(&self, other: &DistributionPy) -> Self {
+        DistributionPy {distribution: self.distribution.min(&other.distribution)}
+    }
+
+    fn map(&self, py: Python, function: PyObject) -> PyResult<Self> {
+        let mut values_and_probas = Vec::with_capacity(self.distribution.len());
+        for (value, proba) in self.distribution.iter() {
+            values_and_probas.push((function.call1(py, (*value,))?.extract::<u32>(py)?, *proba));
+        }
+        Ok(DistributionPy {distribution: Distribution::new(values_and_probas)})
+    }
+
+    fn condition(&self, py: Python, predicate: PyObject) -> PyResult<Self> {
+        let mut kept = Vec::new();
+        for (value, _) in self.distribution.iter() {
+            if predicate.call1(py, (*value,))?.is_true(py)? {
+                kept.push(*value);
+            }
+        }
+        self.distribution
+            .condition(|value| kept.contains(value))
+            .map(|distribution| DistributionPy {distribution})
+            .ok_or_else(|| PyValueError::new_err("Cannot condition on an event of null probability"))
+    }
+
+    fn summary(&self) -> DistributionSummaryPy {
+        self.distribution.summary().into()
+    }
+
+    fn __add__(&self, other: &DistributionPy) -> Self {
+        DistributionPy {distribution: &self.distribution + &other.distribution}
+    }
+
+    fn __len__(&self) -> usize {
+        self.distribution.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Distribution({:?})", self.distribution.values_and_probas())
+    }
+}
+
+impl From<Distribution<u32>> for DistributionPy {
+    fn from(distribution: Distribution<u32>) -> Self {
+        DistributionPy {distribution}
+    }
+}
@@ -0,0 +1,110 @@
+use pyo3::prelude::*;
+
+use crate::probabilities::duel::{Combatant, Duel, DuelResult, compute_duel};
+
+use super::rules::{extract_sequence, run_rules};
+use super::combat_stats::{AttackStatsPy, DefenseStatsPy, RerollsPy, RollModifierPy, TargetUnitPy};
+use super::combat_tree::CombatContextPy;
+use super::wound_allocation::WoundAllocationPy;
+
+// As for weapon profiles, the Python rule objects are kept and extracted on each computation
+#[pyclass(name="Combatant")]
+#[derive(Clone, Debug)]
+pub struct CombatantPy {
+    pub unit: TargetUnitPy,
+    pub attack_stats: AttackStatsPy,
+    pub defense_stats: DefenseStatsPy,
+    pub sequence: Vec<PyObject>,
+    pub roll_modifier: Option<RollModifierPy>,
+    pub rerolls: Option<RerollsPy>,
+    pub context: Option<CombatContextPy>,
+    pub strike_first: bool,
+    pub strike_last: bool,
+}
+
+#[pymethods]
+impl CombatantPy {
+    #[new]
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (
+        unit, attack_stats, defense_stats, sequence, roll_modifier=None, rerolls=None, strike_first=false,
+        strike_last=false, context=None
+    ))]
+    fn new(
+        unit: TargetUnitPy,
+        attack_stats: AttackStatsPy,
+        defense_stats: DefenseStatsPy,
+        sequence: Vec<&PyAny>,
+        roll_modifier: Option<RollModifierPy>,
+        rerolls: Option<RerollsPy>,
+        strike_first: bool,
+        strike_last: bool,
+        context: Option<CombatContextPy>
+    ) -> PyResult<Self> {
+        // Fail early on an invalid sequence
+        extract_sequence(sequence.clone())?;
+        Ok(CombatantPy {
+            unit,
+            attack_stats,
+            defense_stats,
+            sequence: sequence.into_iter().map(|rule| rule.into()).collect(),
+            roll_modifier,
+            rerolls,
+            context,
+            strike_first,
+            strike_last,
+        })
+    }
+}
+
+impl CombatantPy {
+    pub fn combatant(&self, py: Python) -> PyResult<Combatant> {
+        let sequence = extract_sequence(self.sequence.iter().map(|rule| rule.as_ref(py)).collect())?;
+        let mut combatant = Combatant::new(
+            self.unit.target_unit,
+            self.attack_stats.attack_stats.clone(),
+            self.defense_stats.defense_stats.clone(),
+            sequence
+        )
+        .with_strike_first(self.strike_first)
+        .with_strike_last(self.strike_last);
+        if let Some(modifier) = &self.roll_modifier {
+            combatant = combatant.with_modifier(modifier.roll_modifier);
+        }
+        if let Some(rerolls) = &self.rerolls {
+            combatant = combatant.with_rerolls(rerolls.clone().into());
+        }
+        if let Some(context) = &self.context {
+            combatant = combatant.with_context(context.context.clone());
+        }
+        Ok(combatant)
+    }
+}
+
+#[pyclass(name="DuelResult")]
+#[derive(Clone, Debug)]
+pub struct DuelResultPy {
+    pub result: DuelResult
+}
+
+#[pymethods]
+impl DuelResultPy {
+    /// Joint distribution of the models slain in the first and second units.
+    fn losses(&self) -> Vec<((u32, u32), f64)> {
+        self.result.losses().values_and_probas()
+    }
+
+    fn first_allocation(&self) -> WoundAllocationPy {
+        WoundAllocationPy {allocation: self.result.first_allocation()}
+    }
+
+    fn second_allocation(&self) -> WoundAllocationPy {
+        WoundAllocationPy {allocation: self.result.second_allocation()}
+    }
+}
+
+#[pyfunction(name="compute_duel")]
+pub fn compute_duel_py(py: Python, first: CombatantPy, second: CombatantPy) -> PyResult<DuelResultPy> {
+    let duel = Duel::new(first.combatant(py)?, second.combatant(py)?);
+    run_rules(|| DuelResultPy {result: compute_duel(&duel)})
+}
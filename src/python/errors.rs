@@ -0,0 +1,36 @@
+use pyo3::create_exception;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use crate::probabilities::errors::StatsError;
+
+// Subclassing ValueError keeps `except ValueError` working for existing scripts
+create_exception!(rs_aos_stats, AosStatsError, PyValueError, "Base class of the errors raised by rs_aos_stats.");
+create_exception!(rs_aos_stats, DiceParseError, AosStatsError, "A dice expression could not be parsed.");
+create_exception!(rs_aos_stats, InvalidStatsError, AosStatsError, "A statistic has a value outside of its domain.");
+create_exception!(rs_aos_stats, UnknownRuleError, AosStatsError, "An object used in a rule sequence is not a rule.");
+create_exception!(rs_aos_stats, InvalidSequenceError, AosStatsError, "The rules of a sequence are missing a step or are in the wrong order.");
+create_exception!(rs_aos_stats, InvalidProfileError, AosStatsError, "A profile file could not be read or written.");
+
+impl From<StatsError> for PyErr {
+    fn from(error: StatsError) -> PyErr {
+        let message = error.to_string();
+        match error {
+            StatsError::DiceParse(_) => DiceParseError::new_err(message),
+            StatsError::InvalidStats(_) => InvalidStatsError::new_err(message),
+            StatsError::UnknownRule(_) => UnknownRuleError::new_err(message),
+            StatsError::InvalidSequence(_) => InvalidSequenceError::new_err(message),
+            StatsError::InvalidProfile(_) => InvalidProfileError::new_err(message),
+        }
+    }
+}
+
+pub fn register_exceptions(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add("AosStatsError", py.get_type::<AosStatsError>())?;
+    m.add("DiceParseError", py.get_type::<DiceParseError>())?;
+    m.add("InvalidStatsError", py.get_type::<InvalidStatsError>())?;
+    m.add("UnknownRuleError", py.get_type::<UnknownRuleError>())?;
+    m.add("InvalidSequenceError", py.get_type::<InvalidSequenceError>())?;
+    m.add("InvalidProfileError", py.get_type::<InvalidProfileError>())?;
+    Ok(())
+}
@@ -1,37 +1,101 @@
+// The pyo3 0.19 macros implement traits inside functions, which recent compilers warn about
+#![allow(non_local_definitions)]
+
+mod attacking_unit;
+mod battle;
 mod dice;
+mod distribution;
+mod duel;
 mod combat_stats;
 mod combat_tree;
+mod conditions;
+mod errors;
+mod monte_carlo;
+mod profiles;
 mod rules;
+mod turn;
+mod wound_allocation;
+#[cfg(test)]
+mod tests;
 
 use pyo3::prelude::*;
-use crate::python::dice::{DiceRollPy, D3, D6, ND3, ND6, ND3Plus, ND6Plus};
-use crate::python::combat_stats::{CharacteristicPy, AttackStatsPy, DefenseStatsPy, RollModifierPy};
-use crate::python::combat_tree::{CombatConfigPy, compute_damages_py};
-use crate::python::rules::{HitRulePy, WoundRulePy, SaveRulePy, DamagesRulePy, AttackCharacteristicRulePy};
+use crate::python::dice::{DiceRollPy, D3, D6, ND3, ND6, D3Plus, D6Plus, ND3Plus, ND6Plus, DiceExpressionPy};
+use crate::python::distribution::DistributionPy;
+use crate::python::battle::{BattleResultPy, compute_battle_py, simulate_battle_py};
+use crate::python::duel::{CombatantPy, DuelResultPy, compute_duel_py};
+use crate::python::errors::register_exceptions;
+use crate::python::combat_stats::{CharacteristicPy, AttackStatsPy, DefenseStatsPy, RollModifierPy, RerollsPy, TargetProfilePy, TargetUnitPy, CriticalBonusPy, WardPy, DamageReductionPy};
+use crate::python::conditions::{ConditionPy, ConditionalRulePy};
+use crate::python::combat_tree::{CombatConfigPy, CombatContextPy, CombatStatusPy, DistributionSummaryPy, compute_damages_py, compute_damages_summary_py};
+use crate::python::attacking_unit::{WeaponProfilePy, AttackingUnitPy};
+use crate::python::profiles::{UnitLibraryPy, load_library_py};
+use crate::python::monte_carlo::{SimulationResultPy, simulate_damages_py};
+use crate::python::turn::{PhasePy, TurnPy, compute_turn_py};
+use crate::python::wound_allocation::{WoundAllocationPy, compute_kills_py};
+use crate::python::rules::{RulePy, register_standard_rules, standard_sequence_py, validate_sequence_py};
 
 
 #[pymodule]
-fn rs_aos_stats(_py: Python, m: &PyModule) -> PyResult<()> {
+fn rs_aos_stats(py: Python, m: &PyModule) -> PyResult<()> {
+    register_exceptions(py, m)?;
     m.add_class::<DiceRollPy>()?;  // Now it's called DiceRoll in Python
     m.add_class::<D6>()?;
     m.add_class::<D3>()?;
     m.add_class::<ND6>()?;
     m.add_class::<ND3>()?;
+    m.add_class::<D6Plus>()?;
+    m.add_class::<D3Plus>()?;
     m.add_class::<ND6Plus>()?;
     m.add_class::<ND3Plus>()?;
+    m.add_class::<DiceExpressionPy>()?;
+    m.add_class::<DistributionPy>()?;
     // Add combat stats objects
     m.add_class::<CharacteristicPy>()?;
     m.add_class::<AttackStatsPy>()?;
+    m.add_class::<CriticalBonusPy>()?;
     m.add_class::<DefenseStatsPy>()?;
+    m.add_class::<WardPy>()?;
+    m.add_class::<DamageReductionPy>()?;
     m.add_class::<RollModifierPy>()?;
+    m.add_class::<RerollsPy>()?;
+    m.add_class::<TargetUnitPy>()?;
+    m.add_class::<TargetProfilePy>()?;
     // Add combat trees functions
+    m.add_class::<CombatContextPy>()?;
     m.add_class::<CombatConfigPy>()?;
+    m.add_class::<CombatStatusPy>()?;
     m.add_function(wrap_pyfunction!(compute_damages_py, m)?)?;
+    m.add_class::<DistributionSummaryPy>()?;
+    m.add_function(wrap_pyfunction!(compute_damages_summary_py, m)?)?;
+    // Wound allocation
+    m.add_class::<WoundAllocationPy>()?;
+    m.add_function(wrap_pyfunction!(compute_kills_py, m)?)?;
+    // Turns
+    m.add_class::<PhasePy>()?;
+    m.add_class::<TurnPy>()?;
+    m.add_function(wrap_pyfunction!(compute_turn_py, m)?)?;
+    // Duels
+    m.add_class::<CombatantPy>()?;
+    m.add_class::<DuelResultPy>()?;
+    m.add_function(wrap_pyfunction!(compute_duel_py, m)?)?;
+    m.add_class::<BattleResultPy>()?;
+    m.add_function(wrap_pyfunction!(compute_battle_py, m)?)?;
+    m.add_function(wrap_pyfunction!(simulate_battle_py, m)?)?;
+    // Monte Carlo simulation
+    m.add_class::<SimulationResultPy>()?;
+    m.add_function(wrap_pyfunction!(simulate_damages_py, m)?)?;
+    // Attacking units
+    m.add_class::<WeaponProfilePy>()?;
+    m.add_class::<AttackingUnitPy>()?;
     // Rules
-    m.add_class::<HitRulePy>()?;
-    m.add_class::<WoundRulePy>()?;
-    m.add_class::<SaveRulePy>()?;
-    m.add_class::<DamagesRulePy>()?;
-    m.add_class::<AttackCharacteristicRulePy>()?;
+    register_standard_rules(py, m)?;
+    m.add_class::<RulePy>()?;
+    m.add_class::<ConditionPy>()?;
+    m.add_class::<ConditionalRulePy>()?;
+    m.add_function(wrap_pyfunction!(standard_sequence_py, m)?)?;
+    m.add_function(wrap_pyfunction!(validate_sequence_py, m)?)?;
+    // Profile files
+    m.add_class::<UnitLibraryPy>()?;
+    m.add_function(wrap_pyfunction!(load_library_py, m)?)?;
     Ok(())
 }
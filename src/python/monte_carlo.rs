@@ -0,0 +1,60 @@
+use pyo3::prelude::*;
+use crate::probabilities::monte_carlo::{SimulationResult, simulate_damages};
+
+use super::combat_tree::CombatConfigPy;
+use super::rules::{extract_sequence, run_rules};
+use super::distribution::DistributionPy;
+
+
+#[pyclass(name="SimulationResult")]
+#[derive(Clone, Debug)]
+pub struct SimulationResultPy {
+    pub result: SimulationResult
+}
+
+#[pymethods]
+impl SimulationResultPy {
+    #[getter]
+    fn samples(&self) -> u32 {self.result.samples}
+
+    #[getter]
+    fn mean(&self) -> f64 {self.result.mean}
+
+    #[getter]
+    fn std_dev(&self) -> f64 {self.result.std_dev}
+
+    #[getter]
+    fn distribution(&self) -> DistributionPy {self.result.distribution.clone().into()}
+
+    fn values_and_probas(&self) -> Vec<(u32, f64)> {
+        self.result.distribution.values_and_probas()
+    }
+
+    fn std_error(&self) -> f64 {
+        self.result.std_error()
+    }
+
+    #[pyo3(signature = (confidence=0.95))]
+    fn mean_confidence_interval(&self, confidence: f64) -> PyResult<(f64, f64)> {
+        Ok(self.result.mean_confidence_interval(confidence)?)
+    }
+
+    #[pyo3(signature = (value, confidence=0.95))]
+    fn probability_confidence_interval(&self, value: u32, confidence: f64) -> PyResult<(f64, f64)> {
+        Ok(self.result.probability_confidence_interval(value, confidence)?)
+    }
+
+    #[pyo3(signature = (k, confidence=0.95))]
+    fn probability_at_least_confidence_interval(&self, k: u32, confidence: f64) -> PyResult<(f64, f64)> {
+        Ok(self.result.probability_at_least_confidence_interval(k, confidence)?)
+    }
+}
+
+#[pyfunction(name="simulate_damages")]
+#[pyo3(signature = (config, sequence, samples, seed=0))]
+pub fn simulate_damages_py(config: CombatConfigPy, sequence: Vec<&PyAny>, samples: u32, seed: u64) -> PyResult<SimulationResultPy> {
+    let rule_sequence = extract_sequence(sequence)?;
+    run_rules(|| SimulationResultPy {
+        result: simulate_damages(config.into(), &rule_sequence, samples, seed)
+    })
+}
@@ -0,0 +1,111 @@
+use std::path::PathBuf;
+
+use pyo3::exceptions::PyKeyError;
+use pyo3::prelude::*;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::probabilities::conditions::{Condition, ConditionalMode, ConfigChange};
+use crate::probabilities::errors::StatsError;
+use crate::probabilities::profiles::{RuleSpec, UnitLibrary, UnitSpec};
+use crate::probabilities::rules::StandardRule;
+
+use super::attacking_unit::{AttackingUnitPy, WeaponProfilePy};
+use super::combat_stats::{AttackStatsPy, RerollsPy, RollModifierPy, TargetProfilePy};
+use super::combat_tree::CombatContextPy;
+use super::conditions::{ConditionPy, ConditionalRulePy};
+use super::rules::python_rule;
+
+pub fn to_json<T: Serialize>(value: &T) -> PyResult<String> {
+    serde_json::to_string(value).map_err(|error| StatsError::InvalidProfile(error.to_string()).into())
+}
+
+pub fn from_json<T: DeserializeOwned>(text: &str) -> PyResult<T> {
+    serde_json::from_str(text).map_err(|error| StatsError::InvalidProfile(error.to_string()).into())
+}
+
+// Python counterpart of a rule read from a profile file
+fn python_rule_spec(py: Python, spec: &RuleSpec) -> PyObject {
+    match spec {
+        RuleSpec::Anti {keyword, rend} => ConditionalRulePy {
+            condition: ConditionPy {condition: Condition::TargetKeyword(keyword.clone())},
+            rule: python_rule(py, StandardRule::Save),
+            mode: ConditionalMode::Modify(vec![ConfigChange::Rend(*rend)]),
+        }.into_py(py),
+        RuleSpec::Conditional {condition, rule, mode} => ConditionalRulePy {
+            condition: ConditionPy {condition: condition.clone()},
+            rule: python_rule_spec(py, rule),
+            mode: mode.clone(),
+        }.into_py(py),
+        RuleSpec::Standard(rule) => python_rule(py, *rule),
+    }
+}
+
+/// Warscrolls loaded from a TOML or JSON profile file, see the README for the format.
+#[pyclass(name="UnitLibrary")]
+#[derive(Clone, Debug)]
+pub struct UnitLibraryPy {
+    pub library: UnitLibrary
+}
+
+#[pymethods]
+impl UnitLibraryPy {
+    #[staticmethod]
+    fn from_json(text: &str) -> PyResult<Self> {
+        Ok(UnitLibraryPy {library: UnitLibrary::from_json(text)?})
+    }
+
+    #[staticmethod]
+    fn from_toml(text: &str) -> PyResult<Self> {
+        Ok(UnitLibraryPy {library: UnitLibrary::from_toml(text)?})
+    }
+
+    fn to_json(&self) -> PyResult<String> {
+        Ok(self.library.to_json()?)
+    }
+
+    fn to_toml(&self) -> PyResult<String> {
+        Ok(self.library.to_toml()?)
+    }
+
+    /// Writes a `.json` or `.toml` file, the format being given by the extension.
+    fn save(&self, path: PathBuf) -> PyResult<()> {
+        Ok(self.library.save(&path)?)
+    }
+
+    fn unit_names(&self) -> Vec<String> {
+        self.library.unit_names()
+    }
+
+    fn target_profile(&self, name: &str) -> PyResult<TargetProfilePy> {
+        Ok(TargetProfilePy {target_profile: self.unit(name)?.target_profile()})
+    }
+
+    /// Unit attacking with all its weapons, the keywords of the unit being attacker keywords.
+    fn attacking_unit(&self, py: Python, name: &str) -> PyResult<AttackingUnitPy> {
+        let unit = self.unit(name)?;
+        let profiles = unit.weapons.iter().map(|weapon| {
+            let context = unit.weapon_profile(weapon)?.context;
+            Ok(WeaponProfilePy {
+                attack_stats: AttackStatsPy {attack_stats: weapon.attack_stats.clone()},
+                sequence: weapon.sequence.iter().map(|spec| python_rule_spec(py, spec)).collect(),
+                roll_modifier: Some(RollModifierPy {roll_modifier: weapon.modifier}),
+                rerolls: Some(RerollsPy {rerolls: weapon.rerolls}),
+                context: Some(CombatContextPy {context}),
+            })
+        }).collect::<PyResult<Vec<WeaponProfilePy>>>()?;
+        Ok(AttackingUnitPy {profiles})
+    }
+}
+
+impl UnitLibraryPy {
+    fn unit(&self, name: &str) -> PyResult<&UnitSpec> {
+        self.library.unit(name).ok_or_else(|| PyKeyError::new_err(format!("No unit named '{}'", name)))
+    }
+}
+
+/// Reads a `.json` or `.toml` profile file.
+#[pyfunction(name="load_library")]
+pub fn load_library_py(path: PathBuf) -> PyResult<UnitLibraryPy> {
+    Ok(UnitLibraryPy {library: UnitLibrary::load(&path)?})
+}
@@ -1,10 +1,21 @@
+use std::cell::RefCell;
+use std::fmt;
+
+use pyo3::exceptions::PyNotImplementedError;
 use pyo3::prelude::*;
 use crate::probabilities::rules::{
     HitRule, WoundRule, SaveRule, DamagesRule, AttackCharacteristicRule,
-    WardRule, CritAutoWoundRule, CritMortalWoundRule, CritDoubleHitRule
+    WardRule, CritAutoWoundRule, CritMortalWoundRule, CritDoubleHitRule, StandardRule
+};
+
+use crate::probabilities::combat_tree::{
+    CombatConfig, CombatStatus, CriticalHitEffect, Rule, SequenceBuilder
 };
+use crate::probabilities::distribution::Distribution;
+use crate::probabilities::errors::StatsError;
 
-use crate::probabilities::combat_tree::Rule;
+use super::combat_tree::{CombatConfigPy, CombatStatusPy};
+use super::conditions::ConditionalRulePy;
 
 
 #[pyclass(name="HitRule")]
@@ -152,59 +163,203 @@ impl Into<CritDoubleHitRule> for CritDoubleHitRulePy {
     }
 }
 
+/// Base class of the rules written in Python, which override `apply`.
+#[pyclass(name="Rule", subclass)]
+#[derive(Clone, Debug)]
+pub struct RulePy;
+
+#[pymethods]
+impl RulePy {
+    #[new]
+    fn new() -> Self {Self {}}
+
+    /// Outcomes of the rule as a list of (status, probability), an empty list keeping the status as is.
+    fn apply(&self, _status: CombatStatusPy, _config: CombatConfigPy) -> PyResult<Vec<(CombatStatusPy, f64)>> {
+        Err(PyNotImplementedError::new_err("Rules must implement apply(status, config)"))
+    }
+}
 
-impl From<&PyAny> for Box<dyn Rule> {
-    fn from(rule: &PyAny) -> Box<dyn Rule> {
+thread_local! {
+    // First error raised by a Python rule during the current computation
+    static PYTHON_RULE_ERROR: RefCell<Option<PyErr>> = const { RefCell::new(None) };
+}
+
+/// Runs a computation using rule sequences, raising the first error of its Python rules.
+pub fn run_rules<T>(computation: impl FnOnce() -> T) -> PyResult<T> {
+    PYTHON_RULE_ERROR.with(|error| error.borrow_mut().take());
+    let result = computation();
+    match PYTHON_RULE_ERROR.with(|error| error.borrow_mut().take()) {
+        Some(error) => Err(error),
+        None => Ok(result)
+    }
+}
+
+/// Rule implemented by a Python subclass of `Rule`.
+///
+/// As `Rule::apply` cannot fail, errors are stored until the end of the computation,
+/// the status being kept as is.
+pub struct PythonRule {
+    rule: PyObject,
+}
+
+impl PythonRule {
+    fn outcomes(&self, py: Python, status: &CombatStatus, config: &CombatConfig) -> PyResult<Distribution<CombatStatus>> {
+        let args = (CombatStatusPy {status: *status}, CombatConfigPy {config: config.clone()});
+        let outcomes: Vec<(CombatStatusPy, f64)> = self.rule.as_ref(py).call_method1("apply", args)?.extract()?;
+        if let Some((_, proba)) = outcomes.iter().find(|(_, proba)| !(*proba >= 0.0 && proba.is_finite())) {
+            return Err(StatsError::InvalidStats(format!("Rule {:?} returned the probability {}", self, proba)).into());
+        }
+        Ok(match outcomes.is_empty() {
+            true => Distribution::certain(*status),
+            false => Distribution::new(outcomes.into_iter().map(|(status, proba)| (status.status, proba)).collect())
+        })
+    }
+}
+
+impl fmt::Debug for PythonRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Python::with_gil(|py| match self.rule.as_ref(py).get_type().name() {
+            Ok(name) => write!(f, "{}", name),
+            Err(_) => write!(f, "PythonRule"),
+        })
+    }
+}
+
+impl Rule for PythonRule {
+    fn apply(&self, status: &CombatStatus, config: &CombatConfig) -> Distribution<CombatStatus> {
+        if PYTHON_RULE_ERROR.with(|error| error.borrow().is_some()) {
+            return Distribution::certain(*status);
+        }
+        Python::with_gil(|py| self.outcomes(py, status, config)).unwrap_or_else(|error| {
+            PYTHON_RULE_ERROR.with(|stored| *stored.borrow_mut() = Some(error));
+            Distribution::certain(*status)
+        })
+    }
+}
+
+
+impl TryFrom<&PyAny> for Box<dyn Rule> {
+    type Error = StatsError;
+
+    fn try_from(rule: &PyAny) -> Result<Box<dyn Rule>, StatsError> {
         if let Ok(rule) = rule.extract::<HitRulePy>() {
             let rule: HitRule = rule.into();
             let rule: Box<dyn Rule> = Box::new(rule);
-            rule
+            Ok(rule)
         }
         else if let Ok(rule) = rule.extract::<WoundRulePy>() {
             let rule: WoundRule = rule.into();
             let rule: Box<dyn Rule> = Box::new(rule);
-            rule
+            Ok(rule)
         }
         else if let Ok(rule) = rule.extract::<SaveRulePy>() {
             let rule: SaveRule = rule.into();
             let rule: Box<dyn Rule> = Box::new(rule);
-            rule
+            Ok(rule)
         }
         else if let Ok(rule) = rule.extract::<DamagesRulePy>() {
             let rule: DamagesRule = rule.into();
             let rule: Box<dyn Rule> = Box::new(rule);
-            rule
+            Ok(rule)
         }
         else if let Ok(rule) = rule.extract::<AttackCharacteristicRulePy>() {
             let rule: AttackCharacteristicRule = rule.into();
             let rule: Box<dyn Rule> = Box::new(rule);
-            rule
+            Ok(rule)
         }
         else if let Ok(rule) = rule.extract::<WardRulePy>() {
             let rule: WardRule = rule.into();
             let rule: Box<dyn Rule> = Box::new(rule);
-            rule
+            Ok(rule)
         }
         else if let Ok(rule) = rule.extract::<CritAutoWoundRulePy>() {
             let rule: CritAutoWoundRule = rule.into();
             let rule: Box<dyn Rule> = Box::new(rule);
-            rule
+            Ok(rule)
         }
         else if let Ok(rule) = rule.extract::<CritMortalWoundRulePy>() {
             let rule: CritMortalWoundRule = rule.into();
             let rule: Box<dyn Rule> = Box::new(rule);
-            rule
+            Ok(rule)
         }
         else if let Ok(rule) = rule.extract::<CritDoubleHitRulePy>() {
             let rule: CritDoubleHitRule = rule.into();
             let rule: Box<dyn Rule> = Box::new(rule);
-            rule
+            Ok(rule)
+        }
+        else if let Ok(conditional_rule) = rule.extract::<ConditionalRulePy>() {
+            let rule: Box<dyn Rule> = Box::new(conditional_rule.conditional_rule(rule.py())?);
+            Ok(rule)
+        }
+        else if rule.is_instance_of::<RulePy>() {
+            let rule: Box<dyn Rule> = Box::new(PythonRule {rule: rule.into()});
+            Ok(rule)
         }
         else {
-            panic!("Unknown rule type")
+            Err(StatsError::UnknownRule(rule.to_string()))
         }
     }
 }
 
+/// Converts a Python list of rules to a rule sequence, whose steps must be in order.
+///
+/// All the computations go through this check, see `SequenceBuilder::custom`.
+pub fn extract_sequence(sequence: Vec<&PyAny>) -> Result<Vec<Box<dyn Rule>>, StatsError> {
+    SequenceBuilder::custom(sequence.into_iter().map(TryInto::try_into).collect::<Result<_, StatsError>>()?)
+}
+
+fn extract_critical_hit_effect(value: Option<&str>) -> Result<CriticalHitEffect, StatsError> {
+    match value {
+        None => Ok(CriticalHitEffect::None),
+        Some("mortal_wound") => Ok(CriticalHitEffect::MortalWound),
+        Some("auto_wound") => Ok(CriticalHitEffect::AutoWound),
+        Some("double_hit") => Ok(CriticalHitEffect::DoubleHit),
+        Some(other) => Err(StatsError::InvalidStats(format!(
+            "Unknown critical hit effect '{}', expected 'mortal_wound', 'auto_wound' or 'double_hit'", other
+        )))
+    }
+}
+
+/// Standard attack sequence, see `SequenceBuilder`.
+#[pyfunction(name="standard_sequence")]
+#[pyo3(signature = (critical_hit=None, damages=true, ward=true))]
+pub fn standard_sequence_py(py: Python, critical_hit: Option<&str>, damages: bool, ward: bool) -> PyResult<Vec<PyObject>> {
+    let builder = SequenceBuilder::new()
+        .with_critical_hit(extract_critical_hit_effect(critical_hit)?)
+        .with_damages(damages)
+        .with_ward(ward);
+    Ok(builder.standard_rules().into_iter().map(|rule| python_rule(py, rule)).collect())
+}
 
+// Python counterpart of the rules provided by the crate
+pub fn python_rule(py: Python, rule: StandardRule) -> PyObject {
+    match rule {
+        StandardRule::AttackCharacteristic => AttackCharacteristicRulePy.into_py(py),
+        StandardRule::Hit => HitRulePy.into_py(py),
+        StandardRule::CritMortalWound => CritMortalWoundRulePy.into_py(py),
+        StandardRule::CritAutoWound => CritAutoWoundRulePy.into_py(py),
+        StandardRule::CritDoubleHit => CritDoubleHitRulePy.into_py(py),
+        StandardRule::Wound => WoundRulePy.into_py(py),
+        StandardRule::Save => SaveRulePy.into_py(py),
+        StandardRule::Damages => DamagesRulePy.into_py(py),
+        StandardRule::Ward => WardRulePy.into_py(py),
+    }
+}
 
+/// Registers the Python class of every rule listed by `StandardRule::ALL`.
+pub fn register_standard_rules(py: Python, m: &PyModule) -> PyResult<()> {
+    for rule in StandardRule::ALL {
+        let class = python_rule(py, rule).into_ref(py).get_type();
+        m.add(class.name()?, class)?;
+    }
+    Ok(())
+}
+
+/// Raises an `InvalidSequenceError` if the steps of the sequence are missing or out of order.
+///
+/// The computations make the same check, this only allows to check a sequence beforehand.
+#[pyfunction(name="validate_sequence")]
+pub fn validate_sequence_py(sequence: Vec<&PyAny>) -> PyResult<()> {
+    extract_sequence(sequence)?;
+    Ok(())
+}
@@ -0,0 +1,324 @@
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyModule};
+
+use crate::probabilities::combat_tree::{CriticalHitEffect, Rule, SequenceBuilder};
+use crate::probabilities::dice::DiceRoll;
+use crate::probabilities::profiles::UnitLibrary;
+use crate::probabilities::profiles::tests::readme_profile;
+use crate::probabilities::rules::StandardRule;
+
+use super::rules::python_rule;
+
+/// Python class of each dice, the match failing to compile when a variant is added.
+fn python_dice_class(dice: &DiceRoll) -> &'static str {
+    match dice {
+        DiceRoll::D6 => "D6",
+        DiceRoll::D3 => "D3",
+        DiceRoll::ND6(_) => "ND6",
+        DiceRoll::ND3(_) => "ND3",
+        DiceRoll::D6Plus(_) => "D6Plus",
+        DiceRoll::D3Plus(_) => "D3Plus",
+        DiceRoll::ND3Plus(_, _) => "ND3Plus",
+        DiceRoll::ND6Plus(_, _) => "ND6Plus",
+        DiceRoll::Expression(_) => "DiceExpression",
+    }
+}
+
+const DICE_SAMPLES: [&str; 9] = ["D6", "D3", "2D6", "2D3", "D6+1", "D3+1", "2D3+1", "2D6+1", "max(D6, 3)"];
+
+fn with_module(test: impl FnOnce(Python, &PyModule) -> PyResult<()>) {
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(|py| {
+        let module = PyModule::new(py, "rs_aos_stats")?;
+        super::rs_aos_stats(py, module)?;
+        test(py, module)
+    })
+    .unwrap();
+}
+
+/// Instance of a class of the module built without arguments, such as a rule.
+fn instance<'py>(module: &'py PyModule, name: &str) -> &'py PyAny {
+    module.getattr(name).unwrap().call0().unwrap()
+}
+
+/// Rules of the module, given by their class names.
+fn rules<'py>(module: &'py PyModule, names: &[&str]) -> Vec<&'py PyAny> {
+    names.iter().map(|name| instance(module, name)).collect()
+}
+
+/// `CombatConfig` of attacks without rend made against a 4+ save.
+fn combat_config<'py>(py: Python, module: &'py PyModule, attacks: u32) -> PyResult<&'py PyAny> {
+    module.getattr("CombatConfig")?.call1((
+        module.getattr("AttackStats")?.call1((attacks, 3, 4, 0, 1))?,
+        module.getattr("DefenseStats")?.call1((4, py.None()))?,
+    ))
+}
+
+fn assert_raises(py: Python, result: PyResult<&PyAny>, exception: &PyAny) {
+    let error = result.expect_err("expected an exception");
+    assert!(error.matches(py, exception), "{} is not a {}", error, exception);
+}
+
+#[test]
+fn every_rule_has_a_python_binding() {
+    with_module(|py, module| {
+        let charged = module.getattr("Condition")?.call_method1("flag", ("charged",))?;
+        for standard_rule in StandardRule::ALL {
+            let py_rule = python_rule(py, standard_rule).into_ref(py);
+            let class = py_rule.get_type();
+            assert!(module.getattr(class.name()?)?.is(class), "Rule {} is not registered in the Python module", class);
+            let rule = Box::<dyn Rule>::try_from(py_rule)?;
+            assert_eq!(rule.standard_rule(), Some(standard_rule));
+
+            let conditional = module.getattr("ConditionalRule")?.call1((charged, py_rule))?;
+            let rule = Box::<dyn Rule>::try_from(conditional)?;
+            assert!(format!("{:?}", rule).starts_with("ConditionalRule"), "{:?}", rule);
+            assert_eq!(rule.stage(), standard_rule.rule().stage());
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn every_dice_has_a_python_binding() {
+    with_module(|_py, module| {
+        let dice_roll = module.getattr("DiceRoll")?;
+        for dice_str in DICE_SAMPLES {
+            let dice = DiceRoll::from_str(dice_str.to_string()).unwrap();
+            let class = python_dice_class(&dice);
+            assert!(module.hasattr(class)?, "Dice {} is not registered in the Python module", class);
+
+            let py_dice = dice_roll.call_method1("from_str", (dice_str,))?;
+            assert_eq!(py_dice.get_type().name()?, class);
+            let values_and_probas: Vec<(u32, f64)> = py_dice.call_method0("values_and_probas")?.extract()?;
+            assert_eq!(values_and_probas, dice.values_and_probas(), "{}", dice_str);
+            let extracted = DiceRoll::try_from(py_dice)?;
+            assert_eq!(extracted.values_and_probas(), dice.values_and_probas(), "{}", dice_str);
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn invalid_inputs_raise_typed_exceptions() {
+    with_module(|py, module| {
+        let expect_error = |result: PyResult<&PyAny>, exception: &str| {
+            assert_raises(py, result, module.getattr(exception).unwrap());
+        };
+        expect_error(module.getattr("DiceRoll")?.call_method1("from_str", ("2D+1",)), "DiceParseError");
+        expect_error(module.getattr("Characteristic")?.call1(("D7x",)), "DiceParseError");
+        expect_error(module.getattr("Characteristic")?.call1((-1,)), "InvalidStatsError");
+        expect_error(module.getattr("AttackStats")?.call1((2, -3, 4, 1, 1)), "InvalidStatsError");
+        expect_error(module.getattr("Rerolls")?.call1(("sometimes", "ones", "none")), "InvalidStatsError");
+        let sequence = rules(module, &["AttackCharacteristicRule", "HitRule"]);
+        let summary = module.getattr("compute_damages_summary")?.call1((combat_config(py, module, 2)?, sequence))?;
+        expect_error(summary.call_method1("quantile", (1.5,)), "InvalidStatsError");
+
+        let sequence = vec![instance(module, "HitRule"), "WoundRule".to_object(py).into_ref(py)];
+        expect_error(module.getattr("compute_damages")?.call1((combat_config(py, module, 2)?, sequence)), "UnknownRuleError");
+        for exception in ["DiceParseError", "InvalidStatsError", "UnknownRuleError"] {
+            assert!(module.getattr(exception)?.downcast::<pyo3::types::PyType>()?.is_subclass_of::<pyo3::exceptions::PyValueError>()?);
+        }
+        Ok(())
+    });
+}
+
+const PYTHON_RULES: &str = r#"
+class SingleDamage(Rule):
+    def apply(self, status, config):
+        return [(status.with_wounds(0).with_damages(status.wounds), 1.0)]
+
+class Failing(Rule):
+    def apply(self, status, config):
+        raise ZeroDivisionError("failing rule")
+
+class NegativeProbability(Rule):
+    def apply(self, status, config):
+        return [(status, -1.0)]
+
+class NotImplemented(Rule):
+    pass
+"#;
+
+#[test]
+fn python_rules_are_used_in_sequences() {
+    with_module(|py, module| {
+        let globals = PyDict::new(py);
+        globals.set_item("Rule", module.getattr("Rule")?)?;
+        py.run(PYTHON_RULES, Some(globals), None)?;
+        let python_rule = |name: &str| py.eval(&format!("{}()", name), Some(globals), None).unwrap();
+        let config = combat_config(py, module, 4)?;
+        let compute = |last: &PyAny| {
+            let mut sequence = rules(module, &["AttackCharacteristicRule", "HitRule", "WoundRule", "SaveRule"]);
+            sequence.push(last);
+            module.getattr("compute_damages")?.call1((config, sequence))
+        };
+
+        let expected: Vec<(u32, f64)> = compute(instance(module, "DamagesRule"))?.extract()?;
+        let damages: Vec<(u32, f64)> = compute(python_rule("SingleDamage"))?.extract()?;
+        assert_eq!(damages.len(), expected.len());
+        for ((damage, proba), (expected_damage, expected_proba)) in damages.iter().zip(expected.iter()) {
+            assert_eq!(damage, expected_damage);
+            assert!((proba - expected_proba).abs() < 1e-12);
+        }
+
+        assert_raises(py, compute(python_rule("Failing")), py.get_type::<pyo3::exceptions::PyZeroDivisionError>());
+        assert_raises(py, compute(python_rule("NegativeProbability")), module.getattr("InvalidStatsError")?);
+        assert_raises(py, compute(python_rule("NotImplemented")), py.get_type::<pyo3::exceptions::PyNotImplementedError>());
+        Ok(())
+    });
+}
+
+#[test]
+fn sequences_are_built_and_validated() {
+    with_module(|py, module| {
+        let effects = [
+            (py.None(), CriticalHitEffect::None),
+            ("mortal_wound".to_object(py), CriticalHitEffect::MortalWound),
+            ("auto_wound".to_object(py), CriticalHitEffect::AutoWound),
+            ("double_hit".to_object(py), CriticalHitEffect::DoubleHit),
+        ];
+        for (name, effect) in effects {
+            for (damages, ward) in [(true, true), (true, false), (false, true)] {
+                let sequence: Vec<&PyAny> = module.getattr("standard_sequence")?.call1((name.clone_ref(py), damages, ward))?.extract()?;
+                let rules = super::rules::extract_sequence(sequence)?;
+                let expected = SequenceBuilder::new().with_critical_hit(effect).with_damages(damages).with_ward(ward).build();
+                assert_eq!(format!("{:?}", rules), format!("{:?}", expected));
+            }
+        }
+
+        // Orders are checked by the Rust tests, only the bindings are checked here
+        let validate = module.getattr("validate_sequence")?;
+        let globals = PyDict::new(py);
+        globals.set_item("Rule", module.getattr("Rule")?)?;
+        py.run("class Attacks(Rule):\n    def apply(self, status, config):\n        return []", Some(globals), None)?;
+        let mut custom_steps = vec![py.eval("Attacks()", Some(globals), None)?];
+        custom_steps.extend(rules(module, &["HitRule", "WoundRule"]));
+        validate.call1((custom_steps,))?;
+        let out_of_order = rules(module, &["AttackCharacteristicRule", "HitRule", "SaveRule", "WoundRule"]);
+        assert_raises(py, validate.call1((out_of_order.clone(),)), module.getattr("InvalidSequenceError")?);
+
+        // Computations check the order of the steps as well
+        let damages = module.getattr("compute_damages")?.call1((combat_config(py, module, 2)?, out_of_order));
+        assert_raises(py, damages, module.getattr("InvalidSequenceError")?);
+        Ok(())
+    });
+}
+
+#[test]
+fn conditional_rules_follow_the_context() {
+    with_module(|py, module| {
+        let condition = module.getattr("Condition")?;
+        let charged = condition.call_method1("flag", ("charged",))?;
+        let not_monster = condition.call_method1("target_keyword", ("monster",))?.call_method0("__invert__")?;
+        let conditional_rule = |condition: &PyAny, rule: &str, kwargs: Option<&PyDict>| {
+            module.getattr("ConditionalRule").unwrap().call((condition, instance(module, rule)), kwargs).unwrap()
+        };
+        let compute = |context: &PyAny, modifier: (i32, i32, i32), sequence: Vec<&PyAny>| -> PyResult<Vec<(u32, f64)>> {
+            let config = module.getattr("CombatConfig")?.call1((
+                module.getattr("AttackStats")?.call1((4, 3, 4, 1, 1))?,
+                module.getattr("DefenseStats")?.call1((4, 5))?,
+                module.getattr("RollModifier")?.call1(modifier)?,
+                py.None(),
+                context,
+            ))?;
+            module.getattr("compute_damages")?.call1((config, sequence))?.extract()
+        };
+        let context = |flags: Vec<&str>, target_keywords: Vec<&str>| {
+            module.getattr("CombatContext").unwrap().call1((flags, py.None(), target_keywords)).unwrap()
+        };
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("mode", "modify")?;
+        kwargs.set_item("hit_modifier", 1)?;
+        let sequence = || {
+            let mut sequence = vec![instance(module, "AttackCharacteristicRule"), conditional_rule(charged, "HitRule", Some(kwargs))];
+            sequence.extend(rules(module, &["WoundRule", "SaveRule", "DamagesRule"]));
+            sequence.push(conditional_rule(not_monster, "WardRule", None));
+            sequence
+        };
+        let standard = |ward: bool| -> Vec<&PyAny> {
+            module.getattr("standard_sequence").unwrap().call1((py.None(), true, ward)).unwrap().extract().unwrap()
+        };
+
+        assert_eq!(conditional_rule(charged, "HitRule", Some(kwargs)).getattr("mode")?.extract::<String>()?, "modify");
+        assert_eq!(conditional_rule(charged, "HitRule", None).getattr("mode")?.extract::<String>()?, "apply");
+        let implicit = PyDict::new(py);
+        implicit.set_item("hit_modifier", 1)?;
+        let rule = module.getattr("ConditionalRule")?.call((charged, instance(module, "HitRule")), Some(implicit));
+        assert_raises(py, rule, module.getattr("InvalidStatsError")?);
+
+        // Conditions are checked by the Rust tests, only the conversion of the arguments is checked here
+        assert_eq!(compute(context(vec!["charged"], vec![]), (0, 0, 0), sequence())?, compute(context(vec![], vec![]), (1, 0, 0), standard(true))?);
+        assert_eq!(compute(context(vec![], vec!["MONSTER"]), (0, 0, 0), sequence())?, compute(context(vec![], vec![]), (0, 0, 0), standard(false))?);
+        Ok(())
+    });
+}
+
+#[test]
+fn target_profiles_drive_conditional_rules() {
+    with_module(|py, module| {
+        let defense_stats = module.getattr("DefenseStats")?.call1((4,))?;
+        let compute = |config: &PyAny, save: &PyAny| -> PyResult<Vec<(u32, f64)>> {
+            let mut sequence = rules(module, &["AttackCharacteristicRule", "HitRule", "WoundRule"]);
+            sequence.extend([save, instance(module, "DamagesRule")]);
+            module.getattr("compute_damages")?.call1((config, sequence))?.extract()
+        };
+        let config = |rend: i32, modifier: (i32, i32, i32)| {
+            let attack_stats = module.getattr("AttackStats").unwrap().call1((4, 3, 4, rend, 1)).unwrap();
+            let modifier = module.getattr("RollModifier").unwrap().call1(modifier).unwrap();
+            module.getattr("CombatConfig").unwrap().call1((attack_stats, defense_stats, modifier)).unwrap()
+        };
+        let target = |keywords: Vec<&str>, kwargs: Option<&PyDict>| {
+            module.getattr("TargetProfile").unwrap().call((defense_stats, 10, 1, keywords), kwargs).unwrap()
+        };
+        let against = |target: &PyAny| config(1, (0, 0, 0)).call_method1("against", (target,)).unwrap();
+
+        let monster = target(vec!["MONSTER", "HERO"], None);
+        let anti_monster = module.getattr("ConditionalRule")?.call_method1("anti", ("Monster", 1))?;
+        assert_eq!(compute(against(monster), anti_monster)?, compute(config(2, (0, 0, 0)), instance(module, "SaveRule"))?);
+        assert_eq!(against(monster).getattr("context")?.getattr("target_models")?.extract::<Option<u32>>()?, Some(10));
+
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("hit_modifier", -1)?;
+        kwargs.set_item("rend_reduction", 1)?;
+        let elusive = target(vec![], Some(kwargs));
+        assert_eq!(compute(against(elusive), instance(module, "SaveRule"))?, compute(config(0, (-1, 0, 0)), instance(module, "SaveRule"))?);
+        Ok(())
+    });
+}
+
+#[test]
+fn profile_files_are_loaded_and_saved() {
+    with_module(|py, module| {
+        let library_class = module.getattr("UnitLibrary")?;
+        let library = library_class.call_method1("from_toml", (readme_profile(),))?;
+        assert_eq!(library.call_method0("unit_names")?.extract::<Vec<String>>()?, vec!["Liberators"]);
+
+        let rust_library = UnitLibrary::from_toml(readme_profile())?;
+        let unit = rust_library.unit("Liberators").unwrap();
+        let expected = unit.attacking_unit()?.compute_damages_against(&unit.target_profile()).values_and_probas();
+        let target = library.call_method1("target_profile", ("Liberators",))?;
+        let damages: Vec<(u32, f64)> = library.call_method1("attacking_unit", ("Liberators",))?.call_method1("damages_against", (target,))?.extract()?;
+        assert_eq!(damages, expected);
+
+        let json: String = library.call_method0("to_json")?.extract()?;
+        let reloaded = library_class.call_method1("from_json", (json,))?;
+        assert_eq!(reloaded.call_method0("to_toml")?.extract::<String>()?, library.call_method0("to_toml")?.extract::<String>()?);
+
+        let path = std::env::temp_dir().join(format!("rs_aos_stats_{}.toml", std::process::id()));
+        library.call_method1("save", (path.clone(),))?;
+        let loaded = module.getattr("load_library")?.call1((path.clone(),))?;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.call_method0("to_json")?.extract::<String>()?, library.call_method0("to_json")?.extract::<String>()?);
+
+        let attack_stats = module.getattr("AttackStats")?.call1((2, 3, 4, 1, module.getattr("D3")?.call0()?))?;
+        let json: String = attack_stats.call_method0("to_json")?.extract()?;
+        let reloaded = module.getattr("AttackStats")?.call_method1("from_json", (json.clone(),))?;
+        assert_eq!(reloaded.call_method0("to_json")?.extract::<String>()?, json);
+
+        let invalid_sequence = readme_profile().replace("\"wound\",\n", "");
+        assert_raises(py, library_class.call_method1("from_toml", (invalid_sequence,)), module.getattr("InvalidSequenceError")?);
+        assert_raises(py, module.getattr("load_library")?.call1(("missing.toml",)), module.getattr("InvalidProfileError")?);
+        Ok(())
+    });
+}
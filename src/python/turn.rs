@@ -0,0 +1,87 @@
+use pyo3::prelude::*;
+
+use crate::probabilities::turn::{Phase, Turn, compute_turn};
+
+use super::rules::{extract_sequence, run_rules};
+use super::combat_stats::TargetUnitPy;
+use super::combat_tree::CombatConfigPy;
+use super::wound_allocation::WoundAllocationPy;
+
+// As for weapon profiles, the Python rule objects are kept and extracted on each computation
+#[pyclass(name="Phase")]
+#[derive(Clone, Debug)]
+pub struct PhasePy {
+    pub name: String,
+    pub config: CombatConfigPy,
+    pub sequence: Vec<PyObject>,
+}
+
+#[pymethods]
+impl PhasePy {
+    #[new]
+    fn new(name: String, config: CombatConfigPy, sequence: Vec<&PyAny>) -> PyResult<Self> {
+        // Fail early on an invalid sequence
+        extract_sequence(sequence.clone())?;
+        Ok(PhasePy {
+            name,
+            config,
+            sequence: sequence.into_iter().map(|rule| rule.into()).collect(),
+        })
+    }
+
+    #[getter]
+    fn name(&self) -> String {self.name.clone()}
+}
+
+impl PhasePy {
+    fn phase(&self, py: Python) -> PyResult<Phase> {
+        let sequence = extract_sequence(self.sequence.iter().map(|rule| rule.as_ref(py)).collect())?;
+        Ok(Phase::new(&self.name, self.config.config.clone(), sequence))
+    }
+}
+
+#[pyclass(name="Turn")]
+#[derive(Clone, Debug)]
+pub struct TurnPy {
+    pub phases: Vec<PhasePy>,
+}
+
+#[pymethods]
+impl TurnPy {
+    #[new]
+    fn new(phases: Vec<PhasePy>) -> Self {
+        TurnPy {phases}
+    }
+
+    fn add_phase(&mut self, phase: PhasePy) {
+        self.phases.push(phase);
+    }
+
+    /// Health of the target after each phase.
+    fn run(&self, py: Python, target: TargetUnitPy) -> PyResult<Vec<WoundAllocationPy>> {
+        let turn = self.turn(py)?;
+        run_rules(|| turn
+            .run(target.into())
+            .into_iter()
+            .map(|allocation| WoundAllocationPy {allocation})
+            .collect())
+    }
+}
+
+impl TurnPy {
+    fn turn(&self, py: Python) -> PyResult<Turn> {
+        let phases = self.phases
+            .iter()
+            .map(|phase| phase.phase(py))
+            .collect::<PyResult<Vec<Phase>>>()?;
+        Ok(Turn::new(phases))
+    }
+}
+
+#[pyfunction(name="compute_turn")]
+pub fn compute_turn_py(py: Python, turn: TurnPy, target: TargetUnitPy) -> PyResult<WoundAllocationPy> {
+    let turn = turn.turn(py)?;
+    run_rules(|| WoundAllocationPy {
+        allocation: compute_turn(&turn, target.into())
+    })
+}
@@ -0,0 +1,40 @@
+use pyo3::prelude::*;
+use crate::probabilities::wound_allocation::{WoundAllocation, compute_kills};
+
+use super::combat_stats::TargetUnitPy;
+use super::combat_tree::CombatConfigPy;
+use super::rules::{extract_sequence, run_rules};
+
+
+#[pyclass(name="WoundAllocation")]
+#[derive(Clone, Debug)]
+pub struct WoundAllocationPy {
+    pub allocation: WoundAllocation
+}
+
+#[pymethods]
+impl WoundAllocationPy {
+    fn models_slain(&self) -> Vec<(u32, f64)> {
+        self.allocation.models_slain().values_and_probas()
+    }
+
+    fn remaining_wounds(&self) -> Vec<(u32, f64)> {
+        self.allocation.remaining_wounds().values_and_probas()
+    }
+
+    fn kill_probability(&self, n: u32) -> f64 {
+        self.allocation.kill_probability(n)
+    }
+
+    fn destroyed_probability(&self) -> f64 {
+        self.allocation.destroyed_probability()
+    }
+}
+
+#[pyfunction(name="compute_kills")]
+pub fn compute_kills_py(config: CombatConfigPy, sequence: Vec<&PyAny>, target: TargetUnitPy) -> PyResult<WoundAllocationPy> {
+    let rule_sequence = extract_sequence(sequence)?;
+    run_rules(|| WoundAllocationPy {
+        allocation: compute_kills(config.into(), &rule_sequence, target.into())
+    })
+}